@@ -0,0 +1,239 @@
+//! A background process that keeps derived backup keys in memory, like ssh-agent.
+//!
+//! Clients talk to it over a unix socket with a line based protocol:
+//!
+//! - `GET <keybag uuid>` answers `KEY <hex>` or `NONE`
+//! - `PUT <keybag uuid> <hex>` answers `OK`
+//! - `DEL <keybag uuid>` answers `OK`
+//!
+//! Keys are the passcode keys that unlock every class key of a keybag, and are
+//! forgotten once the timeout passes. The passcode key is held instead of the class
+//! keys themselves: it is what the slow key derivation produces, unwrapping the class
+//! keys with it is cheap and checks that it is still the right key, and it is a single
+//! value per keybag, the same one `--key-file` caches.
+
+use ibackuptool2::*;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Environment variable holding the agent socket path
+pub const AGENT_SOCK_ENV: &str = "IBACKUPTOOL_AGENT_SOCK";
+
+/// How long a client waits on the agent before giving up
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keys held by the agent, with the time they expire
+type KeyStore = Arc<Mutex<HashMap<String, (Vec<u8>, Instant)>>>;
+
+/// The socket of a running agent, if `IBACKUPTOOL_AGENT_SOCK` is set.
+pub fn socket_from_env() -> Option<PathBuf> {
+    std::env::var_os(AGENT_SOCK_ENV).map(PathBuf::from)
+}
+
+/// Pick a socket path for a new agent, in a directory only the current user can read.
+pub fn default_socket() -> std::io::Result<PathBuf> {
+    let base = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir);
+    let dir = base.join(format!("ibackuptool2-agent.{}", std::process::id()));
+
+    use std::os::unix::fs::DirBuilderExt;
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    Ok(dir.join("agent.sock"))
+}
+
+/// Run the agent on `path` until it is killed, forgetting keys after `timeout`.
+pub fn run(path: &Path, timeout: Duration) -> std::io::Result<()> {
+    // A socket left behind by an agent that was killed
+    if path.exists() && UnixStream::connect(path).is_err() {
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+
+    let keys: KeyStore = Arc::new(Mutex::new(HashMap::new()));
+
+    // Drop expired keys, even if no client connects
+    {
+        let keys = keys.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(1));
+            let now = Instant::now();
+            keys.lock()
+                .unwrap()
+                .retain(|_, (_, expires)| *expires > now);
+        });
+    }
+
+    // Each client gets its own thread, so a slow or idle one doesn't hold up the others
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let keys = keys.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = handle_client(stream, &keys, timeout) {
+                        warn!("agent: client error: {}", err);
+                    }
+                });
+            }
+            Err(err) => warn!("agent: failed to accept client: {}", err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Answer every request of a single client connection.
+fn handle_client(stream: UnixStream, keys: &KeyStore, timeout: Duration) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let parts: Vec<&str> = line.split_whitespace().collect();
+
+        let response = match parts.as_slice() {
+            ["GET", uuid] => {
+                let keys = keys.lock().unwrap();
+                match keys.get(*uuid) {
+                    Some((key, expires)) if *expires > Instant::now() => {
+                        format!("KEY {}", hex::encode(key))
+                    }
+                    _ => "NONE".to_string(),
+                }
+            }
+            ["PUT", uuid, key] => match hex::decode(key) {
+                Ok(key) => {
+                    debug!("agent: storing key for {}", uuid);
+                    keys.lock()
+                        .unwrap()
+                        .insert(uuid.to_string(), (key, Instant::now() + timeout));
+                    "OK".to_string()
+                }
+                Err(_) => "ERR invalid key".to_string(),
+            },
+            ["DEL", uuid] => {
+                keys.lock().unwrap().remove(*uuid);
+                "OK".to_string()
+            }
+            _ => "ERR unknown request".to_string(),
+        };
+
+        writeln!(writer, "{}", response)?;
+    }
+
+    Ok(())
+}
+
+/// Send a single request to the agent, and read its response line.
+fn request(path: &Path, request: &str) -> std::io::Result<String> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    writeln!(stream, "{}", request)?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    Ok(response.trim_end().to_string())
+}
+
+/// Try to unlock the keybag with a key held by the agent. Returns true if it worked.
+///
+/// The keybag must have been parsed. Keys that don't unlock the keybag are
+/// removed from the agent.
+pub fn unlock(backup: &mut Backup, path: &Path) -> bool {
+    let uuid = match backup.get_keybag() {
+        Some(keybag) => keybag.uuid.to_string(),
+        None => return false,
+    };
+
+    let key = match request(path, &format!("GET {}", uuid)) {
+        Ok(ref response) if response.starts_with("KEY ") => match hex::decode(&response[4..]) {
+            Ok(key) => key,
+            Err(_) => return false,
+        },
+        Ok(_) => {
+            debug!("agent has no key for keybag {}", uuid);
+            return false;
+        }
+        Err(err) => {
+            warn!("can't reach agent at {}: {}", path.display(), err);
+            return false;
+        }
+    };
+
+    match backup.unlock_keybag_with_key(key) {
+        Ok(_) => {
+            info!("unlocked keybag {} from agent", uuid);
+            true
+        }
+        Err(err) => {
+            warn!("rejecting agent key for keybag {}: {}", uuid, err);
+            if let Err(err) = request(path, &format!("DEL {}", uuid)) {
+                warn!("can't reach agent at {}: {}", path.display(), err);
+            }
+            false
+        }
+    }
+}
+
+/// Hand the passcode key of an unlocked keybag to the agent.
+pub fn store(backup: &Backup, path: &Path) {
+    let keybag = match backup.get_keybag() {
+        Some(keybag) => keybag,
+        None => return,
+    };
+
+    if let Some(ref key) = keybag.key {
+        let req = format!("PUT {} {}", keybag.uuid, hex::encode(key));
+        match request(path, &req) {
+            Ok(ref response) if response == "OK" => {
+                debug!("stored key for keybag {} in agent", keybag.uuid)
+            }
+            Ok(response) => warn!("agent refused key: {}", response),
+            Err(err) => warn!("can't reach agent at {}: {}", path.display(), err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.sock");
+
+        {
+            let path = path.clone();
+            std::thread::spawn(move || run(&path, Duration::from_secs(60)));
+        }
+        while UnixStream::connect(&path).is_err() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let uuid = "7d3f8a5e-0c1b-4f6e-9a2d-3b4c5d6e7f80";
+        assert_eq!(request(&path, &format!("GET {}", uuid)).unwrap(), "NONE");
+        assert_eq!(request(&path, &format!("PUT {} 0a0b", uuid)).unwrap(), "OK");
+        assert_eq!(
+            request(&path, &format!("GET {}", uuid)).unwrap(),
+            "KEY 0a0b"
+        );
+        assert_eq!(request(&path, &format!("DEL {}", uuid)).unwrap(), "OK");
+        assert_eq!(request(&path, &format!("GET {}", uuid)).unwrap(), "NONE");
+        assert!(request(&path, "HELLO").unwrap().starts_with("ERR"));
+
+        // an idle client must not block the others
+        let _idle = UnixStream::connect(&path).unwrap();
+        let start = Instant::now();
+        assert_eq!(request(&path, &format!("GET {}", uuid)).unwrap(), "NONE");
+        assert!(start.elapsed() < CLIENT_TIMEOUT);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::extract::is_inside;
+use chrono::{Datelike, Timelike};
+use ibackuptool2::*;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Archive formats `extract --format` can write.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Format names accepted by `--format`
+    pub const NAMES: [&'static str; 3] = ["tar", "tar.zst", "zip"];
+
+    pub fn from_name(name: &str) -> Option<ArchiveFormat> {
+        match name {
+            "tar" => Some(ArchiveFormat::Tar),
+            "tar.zst" => Some(ArchiveFormat::TarZst),
+            "zip" => Some(ArchiveFormat::Zip),
+            _ => None,
+        }
+    }
+}
+
+/// Something entries can be appended to, one at a time.
+trait ArchiveWriter {
+    fn add_directory(&mut self, path: &str, info: Option<&FileInfo>) -> Result<(), BackupError>;
+
+    fn add_file(
+        &mut self,
+        path: &str,
+        info: Option<&FileInfo>,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> Result<(), BackupError>;
+
+    fn add_symlink(
+        &mut self,
+        path: &str,
+        info: Option<&FileInfo>,
+        target: &str,
+    ) -> Result<(), BackupError>;
+}
+
+/// Permission bits from the manifest, or `default` if they are missing.
+fn permissions(info: Option<&FileInfo>, default: u32) -> u32 {
+    match info.map(|info| info.file_mode().permissions()) {
+        Some(mode) if mode != 0 => mode,
+        _ => default,
+    }
+}
+
+impl<W: Write> ArchiveWriter for tar::Builder<W> {
+    fn add_directory(&mut self, path: &str, info: Option<&FileInfo>) -> Result<(), BackupError> {
+        let mut header = tar_header(info, tar::EntryType::Directory, 0o755);
+        self.append_data(&mut header, format!("{}/", path), std::io::empty())?;
+        Ok(())
+    }
+
+    fn add_file(
+        &mut self,
+        path: &str,
+        info: Option<&FileInfo>,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> Result<(), BackupError> {
+        let mut header = tar_header(info, tar::EntryType::Regular, 0o644);
+        header.set_size(size);
+        let mut counted = CountingReader {
+            inner: reader,
+            count: 0,
+        };
+        self.append_data(&mut header, path, &mut counted)?;
+        check_length(size, counted.count)
+    }
+
+    fn add_symlink(
+        &mut self,
+        path: &str,
+        info: Option<&FileInfo>,
+        target: &str,
+    ) -> Result<(), BackupError> {
+        let mut header = tar_header(info, tar::EntryType::Symlink, 0o755);
+        self.append_link(&mut header, path, target)?;
+        Ok(())
+    }
+}
+
+/// A reader that counts the bytes read through it.
+struct CountingReader<'a> {
+    inner: &'a mut dyn Read,
+    count: u64,
+}
+
+impl Read for CountingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+/// Fail if fewer bytes were written than the entry header announced. The tar writer
+/// pads short entries without complaint, which would leave a corrupt archive.
+fn check_length(size: u64, written: u64) -> Result<(), BackupError> {
+    if written < size {
+        return Err(BackupError::Io(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("read {} of {} bytes", written, size),
+        )));
+    }
+    Ok(())
+}
+
+/// A tar header with the mode, owner and modification time from the manifest.
+fn tar_header(info: Option<&FileInfo>, kind: tar::EntryType, default_mode: u32) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(kind);
+    header.set_size(0);
+    header.set_mode(permissions(info, default_mode));
+    if let Some(info) = info {
+        header.set_mtime(info.last_modified);
+        header.set_uid(info.user_id);
+        header.set_gid(info.group_id);
+    }
+    header
+}
+
+impl<W: Write + Seek> ArchiveWriter for zip::ZipWriter<W> {
+    fn add_directory(&mut self, path: &str, info: Option<&FileInfo>) -> Result<(), BackupError> {
+        zip::ZipWriter::add_directory(self, path, zip_options(info, 0o755))?;
+        Ok(())
+    }
+
+    fn add_file(
+        &mut self,
+        path: &str,
+        info: Option<&FileInfo>,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> Result<(), BackupError> {
+        let options = zip_options(info, 0o644)
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(size >= u32::MAX as u64);
+        self.start_file(path, options)?;
+        let copied = std::io::copy(reader, self)?;
+        check_length(size, copied)
+    }
+
+    fn add_symlink(
+        &mut self,
+        path: &str,
+        info: Option<&FileInfo>,
+        target: &str,
+    ) -> Result<(), BackupError> {
+        zip::ZipWriter::add_symlink(self, path, target, zip_options(info, 0o755))?;
+        Ok(())
+    }
+}
+
+/// Zip entry options with the mode and modification time from the manifest.
+/// Zip can't store dates before 1980, those entries get the zip default date.
+fn zip_options(info: Option<&FileInfo>, default_mode: u32) -> zip::write::FileOptions {
+    let options = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .unix_permissions(permissions(info, default_mode));
+
+    let date = info
+        .and_then(|info| info.last_modified_date())
+        .and_then(|date| {
+            zip::DateTime::from_date_and_time(
+                date.year() as u16,
+                date.month() as u8,
+                date.day() as u8,
+                date.hour() as u8,
+                date.minute() as u8,
+                date.second() as u8,
+            )
+            .ok()
+        });
+
+    match date {
+        Some(date) => options.last_modified_time(date),
+        None => options,
+    }
+}
+
+/// Write files from the backup into `writer`, as `<domain>/<relative path>` entries,
+/// returning the number of files that could not be archived.
+/// Progress goes to stderr, since the archive itself may be written to stdout.
+///
+/// Files that can't be opened are skipped, but once an entry header is written a
+/// failure would leave a corrupt archive, so any error from `writer` stops the run.
+fn write_entries(
+    backup: &Backup,
+    files: &[&BackupFile],
+    writer: &mut dyn ArchiveWriter,
+    stay_in_root: bool,
+) -> Result<usize, BackupError> {
+    let mut sorted = files.to_vec();
+    sorted.sort_by_key(|file| match file.kind() {
+        FileKind::Directory => 0,
+        FileKind::File | FileKind::Unknown => 1,
+        FileKind::Symlink => 2,
+    });
+
+    let mut failed = 0;
+    for file in sorted {
+        let path = format!("{}/{}", file.domain, file.relative_filename);
+        let info = file.fileinfo.as_ref();
+
+        if !is_inside(Path::new(&path)) {
+            warn!("skipping {}: outside of the archive root", path);
+            continue;
+        }
+
+        let result = match file.kind() {
+            FileKind::Directory => writer
+                .add_directory(&path, info)
+                .map(|_| format!("{}/", path)),
+            FileKind::Symlink => match info.and_then(|info| info.target.as_ref()) {
+                Some(target) if stay_in_root && !link_is_inside(&path, target) => {
+                    warn!(
+                        "skipping {}: target {} is outside of the archive root",
+                        path, target
+                    );
+                    continue;
+                }
+                Some(target) => writer
+                    .add_symlink(&path, info, target)
+                    .map(|_| format!("{} -> {}", path, target)),
+                None => {
+                    error!("failed to archive: {}: {}", path, BackupError::NoFileInfo);
+                    failed += 1;
+                    continue;
+                }
+            },
+            FileKind::File | FileKind::Unknown => {
+                let (reader, size) = match open_file(backup, file) {
+                    Ok(opened) => opened,
+                    Err(err) => {
+                        error!("failed to archive: {}: {}", path, err);
+                        failed += 1;
+                        continue;
+                    }
+                };
+                writer
+                    .add_file(&path, info, size, &mut reader.take(size))
+                    .map(|_| format!("{}: {} bytes", path, size))
+            }
+        };
+
+        match result {
+            Ok(entry) => eprintln!("archive: {}", entry),
+            Err(err) => {
+                error!("failed to archive: {}: {}", path, err);
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(failed)
+}
+
+/// Check if a symlink at `path` pointing to `target` stays inside of the archive root.
+fn link_is_inside(path: &str, target: &str) -> bool {
+    let parent = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    is_inside(&parent.join(target))
+}
+
+/// Open a file from the backup for reading, along with its decrypted size.
+fn open_file(backup: &Backup, file: &BackupFile) -> Result<(Box<dyn ReadSeek>, u64), BackupError> {
+    let mut reader = backup.open_file(file)?;
+
+    // entry headers come before the data, so the size has to be known up front
+    let size = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    Ok((reader, size))
+}
+
+/// Open `dest` for writing, or stdout if it is `-`.
+fn output(dest: &str) -> Result<Box<dyn Write>, BackupError> {
+    if dest == "-" {
+        Ok(Box::new(std::io::BufWriter::new(std::io::stdout())))
+    } else {
+        Ok(Box::new(std::fs::File::create(dest)?))
+    }
+}
+
+/// Write files from the backup into a single archive at `dest`, or to stdout if it is `-`,
+/// returning the number of files that could not be archived.
+pub fn write_archive(
+    backup: &Backup,
+    files: &[&BackupFile],
+    dest: &str,
+    format: ArchiveFormat,
+    stay_in_root: bool,
+) -> Result<usize, BackupError> {
+    let failed = match format {
+        ArchiveFormat::Tar => {
+            let mut builder = tar::Builder::new(output(dest)?);
+            let failed = write_entries(backup, files, &mut builder, stay_in_root)?;
+            builder.into_inner()?.flush()?;
+            failed
+        }
+        ArchiveFormat::TarZst => {
+            let mut builder = tar::Builder::new(zstd::Encoder::new(output(dest)?, 0)?);
+            let failed = write_entries(backup, files, &mut builder, stay_in_root)?;
+            builder.into_inner()?.finish()?.flush()?;
+            failed
+        }
+        // the zip writer seeks back to patch entry headers, which a pipe can't do,
+        // so the archive is spooled to a temporary file when writing to stdout
+        ArchiveFormat::Zip if dest == "-" => {
+            let mut writer = zip::ZipWriter::new(tempfile::tempfile()?);
+            let failed = write_entries(backup, files, &mut writer, stay_in_root)?;
+            let mut spool = writer.finish()?;
+            spool.seek(SeekFrom::Start(0))?;
+            let mut out = output(dest)?;
+            std::io::copy(&mut spool, &mut out)?;
+            out.flush()?;
+            failed
+        }
+        ArchiveFormat::Zip => {
+            let mut writer = zip::ZipWriter::new(std::fs::File::create(dest)?);
+            let failed = write_entries(backup, files, &mut writer, stay_in_root)?;
+            writer.finish()?;
+            failed
+        }
+    };
+
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tar_entries() {
+        let mut builder = tar::Builder::new(vec![]);
+        ArchiveWriter::add_directory(&mut builder, "HomeDomain/Library", None).unwrap();
+        ArchiveWriter::add_file(
+            &mut builder,
+            "HomeDomain/Library/a",
+            None,
+            3,
+            &mut &b"abc"[..],
+        )
+        .unwrap();
+        ArchiveWriter::add_symlink(&mut builder, "HomeDomain/Library/b", None, "a").unwrap();
+        let data = builder.into_inner().unwrap();
+
+        let mut archive = tar::Archive::new(&data[..]);
+        let entries: Vec<(String, tar::EntryType, u32)> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let header = entry.header();
+                (
+                    entry.path().unwrap().display().to_string(),
+                    header.entry_type(),
+                    header.mode().unwrap(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    "HomeDomain/Library/".to_string(),
+                    tar::EntryType::Directory,
+                    0o755
+                ),
+                (
+                    "HomeDomain/Library/a".to_string(),
+                    tar::EntryType::Regular,
+                    0o644
+                ),
+                (
+                    "HomeDomain/Library/b".to_string(),
+                    tar::EntryType::Symlink,
+                    0o755
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_short_read() {
+        // a reader that ends before the size in the header must fail the entry
+        let mut builder = tar::Builder::new(vec![]);
+        assert!(ArchiveWriter::add_file(&mut builder, "a", None, 4, &mut &b"abc"[..]).is_err());
+
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        assert!(ArchiveWriter::add_file(&mut writer, "a", None, 4, &mut &b"abc"[..]).is_err());
+    }
+
+    #[test]
+    fn test_link_is_inside() {
+        assert!(link_is_inside("HomeDomain/Library/b", "a"));
+        assert!(link_is_inside("HomeDomain/Library/b", "../../AppDomain/c"));
+        assert!(!link_is_inside("HomeDomain/b", "../../etc/passwd"));
+        assert!(!link_is_inside("HomeDomain/b", "../../../root/x"));
+        assert!(!link_is_inside("HomeDomain/b", "/etc/passwd"));
+    }
+}
@@ -0,0 +1,238 @@
+use crate::metadata;
+use ibackuptool2::*;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How `extract` writes files.
+pub struct ExtractOptions {
+    /// Number of files to decrypt and write in parallel
+    pub jobs: usize,
+
+    /// Restore timestamps, permissions and extended attributes
+    pub preserve: bool,
+
+    /// Skip files and symlinks that would end up outside of the output root
+    pub stay_in_root: bool,
+}
+
+/// Resolve `.` and `..` components of a path relative to the output root, without
+/// touching the filesystem. Returns None if the path is absolute, or if a `..`
+/// would climb above the root.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                if !out.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir => {}
+            Component::Normal(name) => out.push(name),
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(out)
+}
+
+/// Check if `path`, relative to the output root, stays inside of it once `..`
+/// components are resolved.
+pub fn is_inside(path: &Path) -> bool {
+    normalize(path).is_some()
+}
+
+/// Where a file from the backup goes, relative to the output root.
+fn relative_path(file: &BackupFile) -> PathBuf {
+    Path::new(&file.domain).join(Path::new(&file.relative_filename))
+}
+
+/// Extract files from the backup to `<root>/<domain>/<relative path>`, returning
+/// the number of files that could not be extracted.
+///
+/// Directories are created first, then regular files are written by `jobs`
+/// workers, and symlinks are created last so no file is written through one.
+/// Directory metadata is restored at the very end, since writing files into
+/// a directory changes its modification time.
+///
+/// `root` should be canonical, so `--stay-in-root` checks can't be fooled by
+/// `..` components in the root itself.
+pub fn extract(
+    backup: &Backup,
+    files: &[&BackupFile],
+    root: &Path,
+    options: &ExtractOptions,
+) -> usize {
+    let mut directories = vec![];
+    let mut regular = vec![];
+    let mut symlinks = vec![];
+
+    for file in files {
+        let relative = relative_path(file);
+        let filepath = root.join(&relative);
+
+        if options.stay_in_root && !is_inside(&relative) {
+            warn!(
+                "skipping {}: outside of the output root",
+                filepath.display()
+            );
+            continue;
+        }
+
+        match file.kind() {
+            FileKind::Directory => directories.push((*file, filepath)),
+            FileKind::Symlink => symlinks.push((*file, filepath)),
+            FileKind::File | FileKind::Unknown => regular.push((*file, filepath)),
+        }
+    }
+
+    let failed = AtomicUsize::new(0);
+    for (_, filepath) in &directories {
+        match std::fs::create_dir_all(filepath) {
+            Ok(_) => println!("extract: {}/", filepath.display()),
+            Err(err) => {
+                error!("failed to extract: {}: {}", filepath.display(), err);
+                failed.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    // Workers take the next file until there are none left
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..options.jobs {
+            scope.spawn(|| {
+                while let Some((file, filepath)) = regular.get(next.fetch_add(1, Ordering::SeqCst))
+                {
+                    match extract_file(backup, file, filepath, options.preserve) {
+                        Ok(size) => println!("extract: {}: {} bytes", filepath.display(), size),
+                        Err(err) => {
+                            error!("failed to extract: {}: {}", filepath.display(), err);
+                            failed.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    for (file, filepath) in &symlinks {
+        match extract_symlink(file, filepath, options) {
+            Ok(target) => println!("extract: {} -> {}", filepath.display(), target),
+            Err(err) => {
+                error!("failed to extract: {}: {}", filepath.display(), err);
+                failed.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    if options.preserve {
+        // deepest first, so restoring a directory doesn't touch its parent afterwards
+        directories.sort_by_key(|(_, filepath)| std::cmp::Reverse(filepath.components().count()));
+        for (file, filepath) in &directories {
+            if let Err(err) = metadata::restore(file, filepath) {
+                warn!(
+                    "failed to restore metadata: {}: {}",
+                    filepath.display(),
+                    err
+                );
+            }
+        }
+    }
+
+    failed.into_inner()
+}
+
+/// Decrypt a single file from the backup to `filepath`, returning the number of bytes written.
+/// With `preserve`, the file metadata from the manifest is restored as well.
+fn extract_file(
+    backup: &Backup,
+    file: &BackupFile,
+    filepath: &Path,
+    preserve: bool,
+) -> Result<u64, BackupError> {
+    let mut reader = backup.open_file(file)?;
+
+    if let Some(parent) = filepath.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut out = std::fs::File::create(filepath)?;
+    let size = std::io::copy(&mut reader, &mut out)?;
+    drop(out);
+
+    if preserve {
+        metadata::restore(file, filepath)?;
+    }
+
+    Ok(size)
+}
+
+/// Recreate a symlink, returning its target.
+fn extract_symlink(
+    file: &BackupFile,
+    filepath: &Path,
+    options: &ExtractOptions,
+) -> Result<String, BackupError> {
+    let target = match file.fileinfo.as_ref().and_then(|info| info.target.as_ref()) {
+        Some(target) => target,
+        None => return Err(BackupError::NoFileInfo),
+    };
+
+    if options.stay_in_root {
+        let relative = relative_path(file);
+        let parent = relative.parent().unwrap_or_else(|| Path::new(""));
+        if !is_inside(&parent.join(target)) {
+            return Err(BackupError::Io(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("target {} is outside of the output root", target),
+            )));
+        }
+    }
+
+    if let Some(parent) = filepath.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    // replace the link from an earlier extraction
+    if std::fs::symlink_metadata(filepath).is_ok() {
+        std::fs::remove_file(filepath)?;
+    }
+
+    create_symlink(target, filepath)?;
+
+    if options.preserve {
+        metadata::restore_symlink(file, filepath)?;
+    }
+
+    Ok(target.to_string())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &str, _path: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "symlinks are only supported on unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_inside() {
+        assert!(is_inside(Path::new("HomeDomain/Library")));
+        assert!(is_inside(Path::new("HomeDomain/../AppDomain/x")));
+        assert!(is_inside(Path::new("HomeDomain/./Library/..")));
+        assert!(!is_inside(Path::new("HomeDomain/../../etc/passwd")));
+        // `..` past the root must not be dropped, even when it comes back in
+        assert!(!is_inside(Path::new("../../../evidence/x")));
+        assert!(!is_inside(Path::new("HomeDomain/../../output/x")));
+        assert!(!is_inside(Path::new("/etc/passwd")));
+    }
+}
@@ -0,0 +1,126 @@
+use clap::{Arg, ArgMatches};
+use ibackuptool2::*;
+
+/// The file filter arguments shared by `ls-files`, `find` and `extract`.
+pub fn args() -> Vec<Arg<'static, 'static>> {
+    let pattern = |name: &'static str, long: &'static str, value: &'static str, help| {
+        Arg::with_name(name)
+            .long(long)
+            .value_name(value)
+            .help(help)
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+    };
+
+    vec![
+        pattern(
+            "DOMAIN",
+            "domain",
+            "GLOB",
+            "Only files in a domain matching this glob.",
+        ),
+        pattern(
+            "EXCLUDE_DOMAIN",
+            "exclude-domain",
+            "GLOB",
+            "Skip files in a domain matching this glob.",
+        ),
+        pattern(
+            "PATH",
+            "path",
+            "GLOB",
+            "Only files with a relativeFilename matching this glob.",
+        ),
+        pattern(
+            "EXCLUDE_PATH",
+            "exclude-path",
+            "GLOB",
+            "Skip files with a relativeFilename matching this glob.",
+        ),
+        pattern(
+            "REGEX",
+            "regex",
+            "REGEX",
+            "Only files where domain/relativeFilename matches this regex.",
+        ),
+        pattern(
+            "EXCLUDE_REGEX",
+            "exclude-regex",
+            "REGEX",
+            "Skip files where domain/relativeFilename matches this regex.",
+        ),
+        Arg::with_name("MIN_SIZE")
+            .long("min-size")
+            .value_name("BYTES")
+            .help("Only files of at least this size.")
+            .takes_value(true),
+        Arg::with_name("MAX_SIZE")
+            .long("max-size")
+            .value_name("BYTES")
+            .help("Only files of at most this size.")
+            .takes_value(true),
+        pattern(
+            "TYPE",
+            "type",
+            "TYPE",
+            "Only files of this type: f (file), d (directory) or l (symlink).",
+        )
+        .possible_values(&["f", "d", "l"]),
+    ]
+}
+
+/// Parse a size argument, exiting with a message if it isn't a number.
+fn parse_size(matches: &ArgMatches, name: &str) -> Option<u64> {
+    matches.value_of(name).map(|value| match value.parse() {
+        Ok(size) => size,
+        Err(_) => {
+            eprintln!("invalid size: {}", value);
+            std::process::exit(1);
+        }
+    })
+}
+
+/// Adds an include or exclude pattern to a filter
+type AddPattern = fn(&mut FileFilter, &str) -> Result<(), BackupError>;
+
+/// Build the file filter from the (sub)command arguments, exiting if a pattern is invalid.
+pub fn from_matches(matches: &ArgMatches) -> FileFilter {
+    let mut filter = FileFilter::new();
+
+    let rules: [(&str, AddPattern); 6] = [
+        ("DOMAIN", FileFilter::include_domain),
+        ("EXCLUDE_DOMAIN", FileFilter::exclude_domain),
+        ("PATH", FileFilter::include_path),
+        ("EXCLUDE_PATH", FileFilter::exclude_path),
+        ("REGEX", FileFilter::include_regex),
+        ("EXCLUDE_REGEX", FileFilter::exclude_regex),
+    ];
+
+    for (name, add) in rules.iter() {
+        for value in matches.values_of(name).into_iter().flatten() {
+            if let Err(err) = add(&mut filter, value) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(size) = parse_size(matches, "MIN_SIZE") {
+        filter.min_size(size);
+    }
+
+    if let Some(size) = parse_size(matches, "MAX_SIZE") {
+        filter.max_size(size);
+    }
+
+    for kind in matches.values_of("TYPE").into_iter().flatten() {
+        filter.include_kind(match kind {
+            "d" => FileKind::Directory,
+            "l" => FileKind::Symlink,
+            _ => FileKind::File,
+        });
+    }
+
+    filter
+}
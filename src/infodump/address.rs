@@ -1,3 +1,5 @@
+use crate::infodump::SqliteProxy;
+use ibackuptool2::*;
 use rusqlite::{Connection, NO_PARAMS};
 use std::collections::HashMap;
 
@@ -368,6 +370,66 @@ pub fn find_listed_properties(
     Ok(value_iter.flat_map(|v| v).collect())
 }
 
+/// Find the contact a phone number or email address belongs to.
+pub fn find_contact<'a>(index: &'a AddressBookIndexed, sender_id: &str) -> Option<&'a Contact> {
+    if sender_id.contains("@") {
+        match index.raw_search(sender_id) {
+            Some(val) => {
+                trace!("index: got raw: {:?}", val);
+                Some(val[0].as_ref())
+            }
+            None => {
+                trace!("index: no hit for `{}`", sender_id);
+                None
+            }
+        }
+    } else {
+        match index.search_via_phone(sender_id) {
+            Some(val) => {
+                trace!("index: got phones: {:?}", val);
+                Some(val[0].as_ref())
+            }
+            None => {
+                trace!("index: no phone hit for `{}`", sender_id);
+                None
+            }
+        }
+    }
+}
+
+/// The full name of a contact, from the name components it has.
+pub fn contact_name(contact: &Contact) -> String {
+    let components = vec![
+        contact.first.as_ref(),
+        contact.middle.as_ref(),
+        contact.last.as_ref(),
+    ];
+
+    components
+        .into_iter()
+        .flatten()
+        .map(|component| component.as_str())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Where the address book is stored in a backup
+pub const ADDRESS_BOOK: (&str, &str) = ("HomeDomain", "Library/AddressBook/AddressBook.sqlitedb");
+
+/// Load the address book of a backup, to resolve names of phone numbers and email
+/// addresses. Backups without an address book get an empty one.
+pub fn load_contacts(backup: &Backup) -> Result<AddressBookIndexed, Box<dyn std::error::Error>> {
+    let (domain, path) = ADDRESS_BOOK;
+    if backup.find_path(domain, path).is_none() {
+        eprintln!("no address book in the backup, names will not be resolved");
+        return Ok(AddressBook { people: vec![] }.into_index());
+    }
+
+    let addrproxy = SqliteProxy::new(backup, domain, path)?;
+    let book = load_address_book(&addrproxy.connection)?;
+    Ok(book.into_index())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
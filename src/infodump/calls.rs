@@ -0,0 +1,281 @@
+use crate::infodump::address::{contact_name, find_contact, load_contacts, AddressBookIndexed};
+use crate::infodump::*;
+use crate::output::{write_rows, OutputFormat, Row};
+use ibackuptool2::*;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{Connection, NO_PARAMS};
+use serde_json::{json, Map, Value};
+use std::io::Write;
+
+use chrono::prelude::DateTime;
+use chrono::Utc;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Seconds between the unix epoch and 2001-01-01, which call dates count from
+const IPHONE_2001_EPOCH_SECONDS: f64 = 978307200.0;
+
+/// Where the call history is stored in a backup
+const CALL_HISTORY_DB: (&str, &str) = ("HomeDomain", "Library/CallHistoryDB/CallHistory.storedata");
+
+/// Columns of `calls.csv`
+const CALL_COLUMNS: [&str; 8] = [
+    "call_id",
+    "date",
+    "duration",
+    "direction",
+    "answered",
+    "type",
+    "address",
+    "name",
+];
+
+/// A phone or FaceTime call from the call history.
+#[derive(Debug)]
+pub struct Call {
+    rowid: i64,
+    /// Phone number or email address of the other side
+    address: Option<String>,
+    /// Seconds since 2001
+    date: Option<f64>,
+    /// Length of the call in seconds
+    duration: f64,
+    originated: bool,
+    answered: bool,
+    call_type: i64,
+}
+
+impl Call {
+    fn kind(&self) -> &'static str {
+        match self.call_type {
+            1 => "phone",
+            8 => "facetime_video",
+            16 => "facetime_audio",
+            _ => "unknown",
+        }
+    }
+
+    fn direction(&self) -> &'static str {
+        if self.originated {
+            "outgoing"
+        } else {
+            "incoming"
+        }
+    }
+
+    fn date(&self) -> Option<DateTime<Utc>> {
+        let seconds = IPHONE_2001_EPOCH_SECONDS + self.date?;
+        if seconds < 0.0 {
+            return None;
+        }
+
+        Some(DateTime::<Utc>::from(
+            UNIX_EPOCH + Duration::from_secs_f64(seconds),
+        ))
+    }
+}
+
+/// The address of a call as text. Depending on the iOS version it is stored as text or as a blob.
+fn address_text(value: SqlValue) -> Option<String> {
+    match value {
+        SqlValue::Text(text) => Some(text),
+        SqlValue::Blob(blob) => Some(String::from_utf8_lossy(&blob).to_string()),
+        SqlValue::Integer(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+pub fn read_calls(conn: &Connection) -> Result<Vec<Call>, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare(
+        "SELECT Z_PK, ZADDRESS, ZDATE, ZDURATION, ZORIGINATED, ZANSWERED, ZCALLTYPE FROM ZCALLRECORD ORDER BY ZDATE",
+    )?;
+
+    let call_iter = stmt.query_map(NO_PARAMS, |row| {
+        let originated: Option<i64> = row.get(4)?;
+        let answered: Option<i64> = row.get(5)?;
+        let duration: Option<f64> = row.get(3)?;
+        let call_type: Option<i64> = row.get(6)?;
+
+        Ok(Call {
+            rowid: row.get(0)?,
+            address: address_text(row.get(1)?),
+            date: row.get(2)?,
+            duration: duration.unwrap_or(0.0),
+            originated: originated == Some(1),
+            answered: answered == Some(1),
+            call_type: call_type.unwrap_or(0),
+        })
+    })?;
+
+    Ok(call_iter.collect::<Result<Vec<Call>, _>>()?)
+}
+
+/// The call history.
+pub struct CallsReport;
+
+impl Report for CallsReport {
+    fn name(&self) -> &'static str {
+        "calls"
+    }
+
+    fn description(&self) -> &'static str {
+        "Phone and FaceTime calls, with the names of who was called"
+    }
+
+    fn sources(&self) -> &'static [(&'static str, &'static str)] {
+        &[CALL_HISTORY_DB]
+    }
+
+    fn run(
+        &self,
+        backup: &Backup,
+        format: ReportFormat,
+    ) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        let (domain, path) = CALL_HISTORY_DB;
+        let proxy = SqliteProxy::new(backup, domain, path)?;
+        let calls = CallHistory {
+            calls: read_calls(&proxy.connection)?,
+        };
+
+        to_format(&calls, backup, format)
+    }
+}
+
+pub struct CallHistory {
+    calls: Vec<Call>,
+}
+
+impl CallHistory {
+    fn records(&self, index: &AddressBookIndexed) -> Vec<Map<String, Value>> {
+        self.calls
+            .iter()
+            .map(|call| {
+                let name = call
+                    .address
+                    .as_ref()
+                    .and_then(|address| find_contact(index, address))
+                    .map(contact_name);
+
+                let mut record = Map::new();
+                record.insert("call_id".into(), json!(call.rowid));
+                record.insert(
+                    "date".into(),
+                    json!(call.date().map(|date| date.to_rfc3339())),
+                );
+                record.insert("duration".into(), json!(call.duration));
+                record.insert("direction".into(), json!(call.direction()));
+                record.insert("answered".into(), json!(call.answered));
+                record.insert("type".into(), json!(call.kind()));
+                record.insert("address".into(), json!(call.address));
+                record.insert("name".into(), json!(name));
+                record
+            })
+            .collect()
+    }
+}
+
+impl JSONOutputFormat for CallHistory {
+    fn to_json(&self, backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        let index = load_contacts(backup)?;
+
+        let mut outfile = OutFile::new("calls.json");
+        serde_json::to_writer_pretty(&mut outfile, &self.records(&index))?;
+        writeln!(outfile)?;
+
+        Ok(vec![outfile])
+    }
+}
+
+impl CSVOutputFormat for CallHistory {
+    fn to_csv(&self, backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        let index = load_contacts(backup)?;
+        let rows: Vec<Row> = self
+            .records(&index)
+            .into_iter()
+            .map(|columns| Row {
+                record: Value::Object(columns.clone()),
+                columns,
+            })
+            .collect();
+
+        let mut outfile = OutFile::new("calls.csv");
+        write_rows(&mut outfile, OutputFormat::Csv, &CALL_COLUMNS, &rows)?;
+
+        Ok(vec![outfile])
+    }
+}
+
+impl TextOutputFormat for CallHistory {
+    fn to_text(&self, backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        let index = load_contacts(backup)?;
+        let mut outfile = OutFile::new("calls.txt");
+
+        for call in &self.calls {
+            let timestamp_str = call
+                .date()
+                .map(|date| date.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| "(no date)".to_string());
+            let who = match &call.address {
+                Some(address) => localize_sender_id(&index, address),
+                None => "unknown".to_string(),
+            };
+            let status = match (call.originated, call.answered) {
+                (false, false) => "missed".to_string(),
+                (true, false) => "not answered".to_string(),
+                _ => format!("{:.0}s", call.duration),
+            };
+
+            writeln!(
+                outfile,
+                "{}: {} {} call {} {}: {}",
+                timestamp_str,
+                call.direction(),
+                call.kind(),
+                if call.originated { "to" } else { "from" },
+                who,
+                status
+            )?;
+        }
+
+        Ok(vec![outfile])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_calls() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE ZCALLRECORD (Z_PK INTEGER PRIMARY KEY, ZADDRESS VARCHAR, ZDATE TIMESTAMP, ZDURATION FLOAT, ZORIGINATED INTEGER, ZANSWERED INTEGER, ZCALLTYPE INTEGER);
+             INSERT INTO ZCALLRECORD VALUES (1, '+1 555 123 4567', 600000000, 42.0, 1, 1, 1);
+             INSERT INTO ZCALLRECORD VALUES (2, X'626f62406578616d706c652e636f6d', 600000100, 0, 0, 0, 8);",
+        )
+        .unwrap();
+
+        let history = CallHistory {
+            calls: read_calls(&conn).unwrap(),
+        };
+        let index = AddressBook {
+            people: vec![Contact {
+                rowid: 1,
+                first: Some("Alice".to_string()),
+                middle: None,
+                last: None,
+                phones: vec![(PropertyLabel::Mobile, "(555) 123-4567".to_string())],
+                emails: vec![],
+            }],
+        }
+        .into_index();
+        let records = history.records(&index);
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["date"], "2020-01-06T10:40:00+00:00");
+        assert_eq!(records[0]["direction"], "outgoing");
+        assert_eq!(records[0]["name"], "Alice");
+        assert_eq!(records[1]["address"], "bob@example.com");
+        assert_eq!(records[1]["type"], "facetime_video");
+        assert_eq!(records[1]["name"], Value::Null);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::infodump::address::{
+    contact_name, load_address_book, AddressBook, Contact, ADDRESS_BOOK,
+};
+use crate::infodump::*;
+use crate::output::{write_rows, OutputFormat, Row};
+use ibackuptool2::*;
+use serde_json::{json, Map, Value};
+use std::io::Write;
+
+/// Columns of `contacts.csv`
+const CONTACT_COLUMNS: [&str; 7] = [
+    "contact_id",
+    "name",
+    "first",
+    "middle",
+    "last",
+    "phones",
+    "emails",
+];
+
+/// The address book.
+pub struct ContactsReport;
+
+impl Report for ContactsReport {
+    fn name(&self) -> &'static str {
+        "contacts"
+    }
+
+    fn description(&self) -> &'static str {
+        "Contacts from the address book, with their phone numbers and email addresses"
+    }
+
+    fn sources(&self) -> &'static [(&'static str, &'static str)] {
+        &[ADDRESS_BOOK]
+    }
+
+    fn run(
+        &self,
+        backup: &Backup,
+        format: ReportFormat,
+    ) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        let (domain, path) = ADDRESS_BOOK;
+        let proxy = SqliteProxy::new(backup, domain, path)?;
+        let book = load_address_book(&proxy.connection)?;
+
+        to_format(&book, backup, format)
+    }
+}
+
+/// Phone numbers or email addresses as json objects with their label.
+fn labeled_values(values: &[(PropertyLabel, String)]) -> Value {
+    values
+        .iter()
+        .map(|(label, value)| json!({"label": format!("{:?}", label), "value": value}))
+        .collect()
+}
+
+/// Phone numbers or email addresses as one line, like `555-1234 (Mobile); 555-9876 (Work)`.
+fn labeled_line(values: &[(PropertyLabel, String)]) -> String {
+    values
+        .iter()
+        .map(|(label, value)| format!("{} ({:?})", value, label))
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+fn contact_record(contact: &Contact) -> Map<String, Value> {
+    let mut record = Map::new();
+    record.insert("contact_id".into(), json!(contact.rowid));
+    record.insert("name".into(), json!(contact_name(contact)));
+    record.insert("first".into(), json!(contact.first));
+    record.insert("middle".into(), json!(contact.middle));
+    record.insert("last".into(), json!(contact.last));
+    record
+}
+
+impl JSONOutputFormat for AddressBook {
+    fn to_json(&self, _backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        let contacts: Vec<Value> = self
+            .people
+            .iter()
+            .map(|contact| {
+                let mut record = contact_record(contact);
+                record.insert("phones".into(), labeled_values(&contact.phones));
+                record.insert("emails".into(), labeled_values(&contact.emails));
+                Value::Object(record)
+            })
+            .collect();
+
+        let mut outfile = OutFile::new("contacts.json");
+        serde_json::to_writer_pretty(&mut outfile, &contacts)?;
+        writeln!(outfile)?;
+
+        Ok(vec![outfile])
+    }
+}
+
+impl CSVOutputFormat for AddressBook {
+    fn to_csv(&self, _backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        let rows: Vec<Row> = self
+            .people
+            .iter()
+            .map(|contact| {
+                let mut columns = contact_record(contact);
+                columns.insert("phones".into(), json!(labeled_line(&contact.phones)));
+                columns.insert("emails".into(), json!(labeled_line(&contact.emails)));
+                Row {
+                    record: Value::Object(columns.clone()),
+                    columns,
+                }
+            })
+            .collect();
+
+        let mut outfile = OutFile::new("contacts.csv");
+        write_rows(&mut outfile, OutputFormat::Csv, &CONTACT_COLUMNS, &rows)?;
+
+        Ok(vec![outfile])
+    }
+}
+
+impl TextOutputFormat for AddressBook {
+    fn to_text(&self, _backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        let mut outfile = OutFile::new("contacts.txt");
+
+        for contact in &self.people {
+            writeln!(outfile, "{}", contact_name(contact))?;
+            for (label, phone) in &contact.phones {
+                writeln!(outfile, "  phone ({:?}): {}", label, phone)?;
+            }
+            for (label, email) in &contact.emails {
+                writeln!(outfile, "  email ({:?}): {}", label, email)?;
+            }
+            writeln!(outfile)?;
+        }
+
+        Ok(vec![outfile])
+    }
+}
@@ -1,12 +1,18 @@
 pub mod address;
+pub mod calls;
+pub mod contacts;
 pub mod outputformat;
+pub mod report;
 pub mod sms;
 
 pub use address::*;
+pub use calls::*;
+pub use contacts::*;
 pub use outputformat::*;
+pub use report::*;
 pub use sms::*;
 
-use crate::lib::*;
+use ibackuptool2::*;
 use rusqlite::Connection;
 use std::io::Write;
 
@@ -26,13 +32,13 @@ impl SqliteProxy {
     ) -> Result<SqliteProxy, Box<dyn std::error::Error>> {
         let mut file = match backup.find_path(domain, path) {
             Some(file) => file,
-            None => return Err(crate::lib::BackupError::FileNotFound.into()),
+            None => return Err(BackupError::FileNotFound.into()),
         };
 
-        file.unwrap_file_key(backup);
+        file.unwrap_file_key(backup)?;
         let mut tmpfile = tempfile::NamedTempFile::new()?;
 
-        tmpfile.write(backup.read_file(&file).expect("read to succeed").as_slice())?;
+        tmpfile.write(backup.read_file(&file)?.as_slice())?;
 
         let connection = Connection::open(tmpfile.path())?;
 
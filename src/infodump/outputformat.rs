@@ -1,4 +1,4 @@
-use crate::lib::*;
+use ibackuptool2::*;
 
 pub struct OutFile {
     pub filename: String,
@@ -48,9 +48,51 @@ impl std::io::Write for OutFile {
 }
 
 pub trait JSONOutputFormat {
-    fn to_json(&self) -> Result<Vec<OutFile>, Box<dyn std::error::Error>>;
+    fn to_json(&self, backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>>;
+}
+
+pub trait CSVOutputFormat {
+    fn to_csv(&self, backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>>;
 }
 
 pub trait TextOutputFormat {
     fn to_text(&self, backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>>;
 }
+
+/// Formats reports can be written in, selected with `infodump --format`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Text,
+}
+
+impl ReportFormat {
+    /// Format names accepted by `--format`
+    pub const NAMES: [&'static str; 3] = ["json", "csv", "txt"];
+
+    pub fn from_name(name: &str) -> Option<ReportFormat> {
+        match name {
+            "json" => Some(ReportFormat::Json),
+            "csv" => Some(ReportFormat::Csv),
+            "txt" => Some(ReportFormat::Text),
+            _ => None,
+        }
+    }
+}
+
+/// Write a report's data in the chosen format.
+pub fn to_format<T>(
+    data: &T,
+    backup: &Backup,
+    format: ReportFormat,
+) -> Result<Vec<OutFile>, Box<dyn std::error::Error>>
+where
+    T: JSONOutputFormat + CSVOutputFormat + TextOutputFormat,
+{
+    match format {
+        ReportFormat::Json => data.to_json(backup),
+        ReportFormat::Csv => data.to_csv(backup),
+        ReportFormat::Text => data.to_text(backup),
+    }
+}
@@ -0,0 +1,85 @@
+use crate::infodump::*;
+use ibackuptool2::*;
+
+/// Something `infodump` can extract from a backup, like the messages or call history.
+pub trait Report {
+    /// Short name, used with `--report` and as the output directory
+    fn name(&self) -> &'static str;
+
+    /// One line about what the report contains, for `--list`
+    fn description(&self) -> &'static str;
+
+    /// The `(domain, path)` of the files the report reads. The report is skipped
+    /// if any of them is not in the backup.
+    fn sources(&self) -> &'static [(&'static str, &'static str)];
+
+    /// Read the sources and produce the output files.
+    fn run(
+        &self,
+        backup: &Backup,
+        format: ReportFormat,
+    ) -> Result<Vec<OutFile>, Box<dyn std::error::Error>>;
+}
+
+/// Every report `infodump` knows about, in the order they are run.
+pub fn registry() -> Vec<Box<dyn Report>> {
+    vec![
+        Box::new(SMSReport),
+        Box::new(ContactsReport),
+        Box::new(CallsReport),
+    ]
+}
+
+/// Look up reports by name, keeping the order of `names`.
+/// Returns the first unknown name as the error.
+pub fn select(names: &[&str]) -> Result<Vec<Box<dyn Report>>, String> {
+    let mut available = registry();
+    let mut selected: Vec<Box<dyn Report>> = vec![];
+
+    for name in names {
+        if selected.iter().any(|report| report.name() == *name) {
+            continue;
+        }
+
+        match available.iter().position(|report| report.name() == *name) {
+            Some(index) => selected.push(available.remove(index)),
+            None => return Err(name.to_string()),
+        }
+    }
+
+    Ok(selected)
+}
+
+/// The first source of a report that is not in the backup.
+pub fn missing_source(
+    report: &dyn Report,
+    backup: &Backup,
+) -> Option<(&'static str, &'static str)> {
+    report
+        .sources()
+        .iter()
+        .find(|(domain, path)| backup.find_path(domain, path).is_none())
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let names = |reports: Vec<Box<dyn Report>>| {
+            reports
+                .iter()
+                .map(|report| report.name())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(select(&["calls", "sms", "calls"]).unwrap()),
+            vec!["calls", "sms"]
+        );
+        assert_eq!(names(registry()), vec!["sms", "contacts", "calls"]);
+        assert_eq!(select(&["sms", "notes"]).err(), Some("notes".to_string()));
+    }
+}
@@ -1,7 +1,9 @@
-use crate::infodump::address::Contact;
+use crate::infodump::address::{contact_name, find_contact, load_contacts, AddressBookIndexed};
 use crate::infodump::*;
-use crate::lib::*;
+use crate::output::{write_rows, OutputFormat, Row};
+use ibackuptool2::*;
 use rusqlite::{Connection, NO_PARAMS};
+use serde_json::{json, Map, Value};
 use std::io::Write;
 
 use chrono::prelude::DateTime;
@@ -10,6 +12,9 @@ use std::time::{Duration, UNIX_EPOCH};
 
 const IPHONE_2001_EPOCH: i64 = 978307200000;
 
+/// Where the messages are stored in a backup
+const SMS_DB: (&str, &str) = ("HomeDomain", "Library/SMS/sms.db");
+
 #[derive(Debug)]
 #[allow(unused)]
 pub struct Sender {
@@ -156,86 +161,160 @@ pub fn find_messages(conn: &Connection, chatid: u32) -> Vec<Message> {
     out
 }
 
-pub fn read_chats(conn: &Connection) -> Vec<Conversation> {
-    let mut stmt = conn
-        .prepare("SELECT rowid, guid, chat_identifier, display_name, group_id FROM chat")
-        .unwrap();
-    let chat_iter = stmt
-        .query_map(NO_PARAMS, |row| {
-            let chat_id: u32 = row.get(0)?;
-            Ok(Conversation {
-                id: chat_id,
-                guid: row.get(1)?,
-                chat_identifier: row.get(2)?,
-                display_name: row.get(3)?,
-                group_id: row.get(4)?,
-                participants: find_people(&conn, chat_id),
-                messages: find_messages(&conn, chat_id),
-            })
+pub fn read_chats(conn: &Connection) -> Result<Vec<Conversation>, rusqlite::Error> {
+    let mut stmt =
+        conn.prepare("SELECT rowid, guid, chat_identifier, display_name, group_id FROM chat")?;
+    let chat_iter = stmt.query_map(NO_PARAMS, |row| {
+        let chat_id: u32 = row.get(0)?;
+        Ok(Conversation {
+            id: chat_id,
+            guid: row.get(1)?,
+            chat_identifier: row.get(2)?,
+            display_name: row.get(3)?,
+            group_id: row.get(4)?,
+            participants: find_people(&conn, chat_id),
+            messages: find_messages(&conn, chat_id),
         })
-        .unwrap();
+    })?;
 
     let convos: Vec<Conversation> = chat_iter.flat_map(|v| v).collect();
 
-    convos
+    Ok(convos)
 }
 
 pub fn localize_sender_id(
     index: &crate::infodump::address::AddressBookIndexed,
     original_sender_name: &str,
 ) -> String {
-    let mut sender_name = original_sender_name.to_string();
-    let mut person: Option<Box<&Contact>> = None;
-
-    if sender_name.contains("@") {
-        match index.raw_search(&sender_name) {
-            Some(val) => {
-                trace!("index: got raw: {:?}", val);
-                person = Some(Box::new(val[0].as_ref()));
-            }
-            None => {
-                trace!("index: no hit for `{}`", sender_name);
-            }
-        }
-    } else {
-        match index.search_via_phone(&sender_name) {
-            Some(val) => {
-                trace!("index: got phones: {:?}", val);
-                person = Some(Box::new(val[0].as_ref()));
-            }
-            None => {
-                trace!("index: no phone hit for `{}`", sender_name);
-            }
-        }
+    match find_contact(index, original_sender_name) {
+        Some(contact) => format!("{} <{}>", contact_name(contact), original_sender_name),
+        None => original_sender_name.to_string(),
     }
+}
 
-    match person {
-        None => {}
-        Some(contact) => {
-            let components = vec![
-                contact.first.as_ref(),
-                contact.middle.as_ref(),
-                contact.last.as_ref(),
-            ];
-            let outname = components
-                .iter()
-                .flat_map(|v| match v {
-                    Some(v) => Ok(v.clone()),
-                    None => Err(()),
-                })
-                .collect::<Vec<&String>>();
-
-            let mut out_addr = String::new();
-            for component in outname {
-                out_addr.extend(component.chars());
-                out_addr.extend(" ".chars());
-            }
+/// Convert a message timestamp, in nanoseconds since 2001, to a date.
+fn message_date(date: i64) -> DateTime<Utc> {
+    let message_epoch: u64 = ((IPHONE_2001_EPOCH + date / 1000000) as u64) / 1000;
+    trace!("got computed epoch {} to {}", date, message_epoch);
+    DateTime::<Utc>::from(UNIX_EPOCH + Duration::from_secs(message_epoch))
+}
 
-            sender_name = format!("{} <{}>", out_addr.trim_end(), sender_name);
-        }
+/// A message timestamp for json and csv output. Timestamps that were never set are 0, and become null.
+fn date_value(date: i64) -> Value {
+    if date == 0 {
+        return Value::Null;
+    }
+
+    json!(message_date(date).to_rfc3339())
+}
+
+/// The name a chat is shown with: its display name, or else the handle it is with.
+fn chat_name(index: &AddressBookIndexed, chat: &Conversation) -> String {
+    let mut chat_name_display = chat.display_name.clone();
+    if chat_name_display.is_empty() {
+        chat_name_display = chat.chat_identifier.clone();
+    }
+
+    localize_sender_id(index, &chat_name_display)
+}
+
+/// Who sent a message: `me`, or the sender with their contact name.
+fn sender_name(index: &AddressBookIndexed, message: &Message) -> String {
+    if message.is_from_me == Some(true) {
+        return "me".to_string();
     }
 
-    sender_name
+    let sender_name = message
+        .from
+        .as_ref()
+        .unwrap_or(&Sender::unknown())
+        .id
+        .to_string();
+    localize_sender_id(index, &sender_name)
+}
+
+fn conversation_record(index: &AddressBookIndexed, chat: &Conversation) -> Map<String, Value> {
+    let mut record = Map::new();
+    record.insert("chat_id".into(), json!(chat.id));
+    record.insert("guid".into(), json!(chat.guid));
+    record.insert("chat_identifier".into(), json!(chat.chat_identifier));
+    record.insert("display_name".into(), json!(chat.display_name));
+    record.insert("name".into(), json!(chat_name(index, chat)));
+    record.insert("group_id".into(), json!(chat.group_id));
+    record
+}
+
+fn participant_record(index: &AddressBookIndexed, sender: &Sender) -> Map<String, Value> {
+    let mut record = Map::new();
+    record.insert("handle_id".into(), json!(sender.rowid));
+    record.insert("id".into(), json!(sender.id));
+    record.insert(
+        "name".into(),
+        json!(find_contact(index, &sender.id).map(contact_name)),
+    );
+    record.insert("country".into(), json!(sender.country));
+    record.insert("service".into(), json!(sender.service));
+    record
+}
+
+fn message_record(index: &AddressBookIndexed, message: &Message) -> Map<String, Value> {
+    let mut record = Map::new();
+    record.insert("message_id".into(), json!(message.rowid));
+    record.insert("date".into(), date_value(message.date));
+    record.insert("date_read".into(), date_value(message.date_read));
+    record.insert("date_delivered".into(), date_value(message.date_delivered));
+    record.insert("is_from_me".into(), json!(message.is_from_me == Some(true)));
+    record.insert(
+        "from".into(),
+        json!(message.from.as_ref().map(|sender| &sender.id)),
+    );
+    record.insert("sender".into(), json!(sender_name(index, message)));
+    record.insert("text".into(), json!(message.text));
+    record
+}
+
+/// Columns of `conversations.csv`
+const CONVERSATION_COLUMNS: [&str; 6] = [
+    "chat_id",
+    "guid",
+    "chat_identifier",
+    "display_name",
+    "name",
+    "group_id",
+];
+
+/// Columns of `participants.csv`
+const PARTICIPANT_COLUMNS: [&str; 6] = ["chat_id", "handle_id", "id", "name", "country", "service"];
+
+/// Columns of `messages.csv`
+const MESSAGE_COLUMNS: [&str; 9] = [
+    "chat_id",
+    "message_id",
+    "date",
+    "date_read",
+    "date_delivered",
+    "is_from_me",
+    "from",
+    "sender",
+    "text",
+];
+
+/// A csv row of a participant or message, with the chat it belongs to in front.
+fn chat_row(chat: &Conversation, record: Map<String, Value>) -> Row {
+    let mut columns = Map::new();
+    columns.insert("chat_id".into(), json!(chat.id));
+    columns.extend(record);
+
+    Row {
+        record: Value::Object(columns.clone()),
+        columns,
+    }
+}
+
+fn csv_file(name: &str, columns: &[&str], rows: &[Row]) -> std::io::Result<OutFile> {
+    let mut outfile = OutFile::new(name);
+    write_rows(&mut outfile, OutputFormat::Csv, columns, rows)?;
+    Ok(outfile)
 }
 
 pub struct SMSReader {
@@ -244,58 +323,140 @@ pub struct SMSReader {
 
 impl SMSReader {
     pub fn load(backup: &Backup) -> Result<SMSReader, Box<dyn std::error::Error>> {
-        let proxy = SqliteProxy::new(backup, "HomeDomain", "Library/SMS/sms.db")?;
+        let (domain, path) = SMS_DB;
+        let proxy = SqliteProxy::new(backup, domain, path)?;
         let conn = &proxy.connection;
 
-        let chats = read_chats(conn);
+        let chats = read_chats(conn)?;
 
         Ok(SMSReader { chats })
     }
 }
 
+impl SMSReader {
+    /// Every chat as a json object, with its participants and messages.
+    fn json_files(
+        &self,
+        index: &AddressBookIndexed,
+    ) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        let chats: Vec<Value> = self
+            .chats
+            .iter()
+            .map(|chat| {
+                let mut record = conversation_record(index, chat);
+                record.insert(
+                    "participants".into(),
+                    chat.participants
+                        .iter()
+                        .map(|sender| Value::Object(participant_record(index, sender)))
+                        .collect(),
+                );
+                record.insert(
+                    "messages".into(),
+                    chat.messages
+                        .iter()
+                        .map(|message| Value::Object(message_record(index, message)))
+                        .collect(),
+                );
+                Value::Object(record)
+            })
+            .collect();
+
+        let mut outfile = OutFile::new("sms.json");
+        serde_json::to_writer_pretty(&mut outfile, &chats)?;
+        writeln!(outfile)?;
+
+        Ok(vec![outfile])
+    }
+
+    /// The chats, their participants and their messages as three csv tables, joined by `chat_id`.
+    fn csv_files(
+        &self,
+        index: &AddressBookIndexed,
+    ) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        let mut conversations: Vec<Row> = vec![];
+        let mut participants: Vec<Row> = vec![];
+        let mut messages: Vec<Row> = vec![];
+
+        for chat in &self.chats {
+            let columns = conversation_record(index, chat);
+            conversations.push(Row {
+                record: Value::Object(columns.clone()),
+                columns,
+            });
+
+            for sender in &chat.participants {
+                participants.push(chat_row(chat, participant_record(index, sender)));
+            }
+
+            for message in &chat.messages {
+                messages.push(chat_row(chat, message_record(index, message)));
+            }
+        }
+
+        Ok(vec![
+            csv_file("conversations.csv", &CONVERSATION_COLUMNS, &conversations)?,
+            csv_file("participants.csv", &PARTICIPANT_COLUMNS, &participants)?,
+            csv_file("messages.csv", &MESSAGE_COLUMNS, &messages)?,
+        ])
+    }
+}
+
+/// Messages, with their conversations and participants.
+pub struct SMSReport;
+
+impl Report for SMSReport {
+    fn name(&self) -> &'static str {
+        "sms"
+    }
+
+    fn description(&self) -> &'static str {
+        "SMS and iMessage conversations, with their participants and messages"
+    }
+
+    fn sources(&self) -> &'static [(&'static str, &'static str)] {
+        &[SMS_DB]
+    }
+
+    fn run(
+        &self,
+        backup: &Backup,
+        format: ReportFormat,
+    ) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        to_format(&SMSReader::load(backup)?, backup, format)
+    }
+}
+
+impl JSONOutputFormat for SMSReader {
+    fn to_json(&self, backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        self.json_files(&load_contacts(backup)?)
+    }
+}
+
+impl CSVOutputFormat for SMSReader {
+    fn to_csv(&self, backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
+        self.csv_files(&load_contacts(backup)?)
+    }
+}
+
 impl TextOutputFormat for SMSReader {
     fn to_text(&self, backup: &Backup) -> Result<Vec<OutFile>, Box<dyn std::error::Error>> {
-        let addrproxy = SqliteProxy::new(
-            backup,
-            "HomeDomain",
-            "Library/AddressBook/AddressBook.sqlitedb",
-        )?;
-        let book = crate::infodump::address::load_address_book(&addrproxy.connection)?;
-        let index = book.into_index();
+        let index = load_contacts(backup)?;
 
         let mut files: Vec<OutFile> = vec![];
 
         for chat in &self.chats {
-            let mut chat_name_display = chat.display_name.clone();
-            if chat_name_display.len() == 0 {
-                chat_name_display = chat.chat_identifier.clone();
-            }
-
-            chat_name_display = localize_sender_id(&index, &chat_name_display);
+            let chat_name_display = chat_name(&index, chat);
 
             let mut outfile = OutFile::new(&format!("{}.txt", chat_name_display));
 
             for message in &chat.messages {
-                let mut sender_name = message
-                    .from
-                    .as_ref()
-                    .unwrap_or(&Sender::unknown())
-                    .id
-                    .to_string();
-                if message.is_from_me == Some(true) {
-                    sender_name = "me".to_string();
-                } else {
-                    sender_name = localize_sender_id(&index, &sender_name);
-                }
-
-                let message_epoch: u64 =
-                    ((IPHONE_2001_EPOCH + message.date / 1000000) as u64) / 1000;
-                trace!("got computed epoch {} to {}", message.date, message_epoch);
-                let d = UNIX_EPOCH + Duration::from_secs(message_epoch);
-                // Create DateTime from SystemTime
-                let datetime = DateTime::<Utc>::from(d);
+                let sender_name = sender_name(&index, message);
+
                 // Formats the combined date and time with the specified format string.
-                let timestamp_str = datetime.format("%Y-%m-%d %H:%M:%S.%f").to_string();
+                let timestamp_str = message_date(message.date)
+                    .format("%Y-%m-%d %H:%M:%S.%f")
+                    .to_string();
 
                 // Output the final message to the terminal.
                 writeln!(
@@ -315,3 +476,109 @@ impl TextOutputFormat for SMSReader {
         Ok(files)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infodump::address::{AddressBook, PropertyLabel};
+
+    fn reader() -> SMSReader {
+        let alice = Sender {
+            rowid: 1,
+            id: "+15551234567".to_string(),
+            country: "us".to_string(),
+            service: "SMS".to_string(),
+        };
+        let message = |rowid, from: Option<Sender>, text: &str, is_from_me| Message {
+            rowid,
+            from,
+            text: Some(text.to_string()),
+            date: 600000000000000000,
+            date_read: 0,
+            date_delivered: 0,
+            is_from_me: Some(is_from_me),
+        };
+
+        SMSReader {
+            chats: vec![Conversation {
+                id: 7,
+                guid: "SMS;-;+15551234567".to_string(),
+                chat_identifier: "+15551234567".to_string(),
+                display_name: String::new(),
+                group_id: "G1".to_string(),
+                messages: vec![
+                    message(1, Some(Sender::unknown()), "hi, there", false),
+                    message(2, None, "hello", true),
+                ],
+                participants: vec![alice],
+            }],
+        }
+    }
+
+    fn index() -> AddressBookIndexed {
+        AddressBook {
+            people: vec![Contact {
+                rowid: 1,
+                first: Some("Alice".to_string()),
+                middle: None,
+                last: Some("Smith".to_string()),
+                phones: vec![(PropertyLabel::Mobile, "(555) 123-4567".to_string())],
+                emails: vec![],
+            }],
+        }
+        .into_index()
+    }
+
+    #[test]
+    fn test_csv_files() {
+        let files = reader().csv_files(&index()).unwrap();
+        let contents: Vec<(&str, String)> = files
+            .iter()
+            .map(|file| {
+                (
+                    file.filename.as_str(),
+                    String::from_utf8(file.contents().to_vec()).unwrap(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            contents,
+            vec![
+                (
+                    "conversations.csv",
+                    "chat_id,guid,chat_identifier,display_name,name,group_id\n\
+                     7,SMS;-;+15551234567,+15551234567,,Alice Smith <+15551234567>,G1\n"
+                        .to_string()
+                ),
+                (
+                    "participants.csv",
+                    "chat_id,handle_id,id,name,country,service\n\
+                     7,1,+15551234567,Alice Smith,us,SMS\n"
+                        .to_string()
+                ),
+                (
+                    "messages.csv",
+                    "chat_id,message_id,date,date_read,date_delivered,is_from_me,from,sender,text\n\
+                     7,1,2020-01-06T10:40:00+00:00,,,false,unknown,unknown,\"hi, there\"\n\
+                     7,2,2020-01-06T10:40:00+00:00,,,true,,me,hello\n"
+                        .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_files() {
+        let files = reader().json_files(&index()).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].filename, "sms.json");
+
+        let chats: Value = serde_json::from_slice(files[0].contents()).unwrap();
+        assert_eq!(chats[0]["name"], "Alice Smith <+15551234567>");
+        assert_eq!(chats[0]["participants"][0]["name"], "Alice Smith");
+        assert_eq!(chats[0]["messages"][0]["date"], "2020-01-06T10:40:00+00:00");
+        assert_eq!(chats[0]["messages"][0]["date_read"], Value::Null);
+        assert_eq!(chats[0]["messages"][1]["sender"], "me");
+    }
+}
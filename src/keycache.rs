@@ -0,0 +1,140 @@
+use clap::{Arg, ArgMatches};
+use ibackuptool2::*;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Length of the local secret used to wrap keys in a key file
+const SECRET_LENGTH: usize = 32;
+
+/// The global `--key-file` argument.
+pub fn arg() -> Arg<'static, 'static> {
+    Arg::with_name("KEY_FILE")
+        .long("key-file")
+        .value_name("FILE")
+        .help("Caches derived backup keys in this file, so later runs can skip the password.")
+        .takes_value(true)
+        .global(true)
+}
+
+/// The key file path passed to a (sub)command, if any.
+pub fn from_matches(matches: &ArgMatches) -> Option<PathBuf> {
+    matches.value_of("KEY_FILE").map(PathBuf::from)
+}
+
+/// Path of the local secret, `<config dir>/ibackuptool2/secret`.
+fn secret_path() -> std::io::Result<PathBuf> {
+    match dirs::config_dir() {
+        Some(dir) => Ok(dir.join("ibackuptool2").join("secret")),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "can't find the config directory",
+        )),
+    }
+}
+
+/// Read the local secret, creating a random one on first use.
+fn local_secret() -> std::io::Result<Vec<u8>> {
+    let path = secret_path()?;
+
+    if path.exists() {
+        let mut secret = vec![];
+        std::fs::File::open(&path)?.read_to_end(&mut secret)?;
+        if secret.len() != SECRET_LENGTH {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} is not a valid secret", path.display()),
+            ));
+        }
+        return Ok(secret);
+    }
+
+    let mut secret = vec![0u8; SECRET_LENGTH];
+    ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut secret)
+        .map_err(|_| std::io::Error::other("failed to generate secret"))?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&path)?.write_all(&secret)?;
+    debug!("created local secret at {}", path.display());
+
+    Ok(secret)
+}
+
+/// Try to unlock the keybag with a cached key. Returns true if it worked.
+///
+/// The keybag must have been parsed. Entries that don't unlock the keybag
+/// are removed from the key file.
+pub fn unlock(backup: &mut Backup, path: &Path) -> bool {
+    let uuid = match backup.get_keybag() {
+        Some(keybag) => keybag.uuid,
+        None => return false,
+    };
+
+    let result = local_secret()
+        .map_err(BackupError::from)
+        .and_then(|secret| Ok((secret, KeyFile::load(path)?)));
+    let (secret, mut keyfile) = match result {
+        Ok(res) => res,
+        Err(err) => {
+            warn!("can't read key file {}: {}", path.display(), err);
+            return false;
+        }
+    };
+
+    let unlocked = match keyfile.get(&uuid, &secret) {
+        Ok(Some(key)) => backup.unlock_keybag_with_key(key),
+        Ok(None) => {
+            debug!("no cached key for keybag {}", uuid);
+            return false;
+        }
+        Err(err) => Err(err),
+    };
+
+    match unlocked {
+        Ok(_) => {
+            info!("unlocked keybag {} from key file", uuid);
+            true
+        }
+        Err(err) => {
+            warn!("rejecting cached key for keybag {}: {}", uuid, err);
+            keyfile.remove(&uuid);
+            if let Err(err) = keyfile.save(path) {
+                warn!("failed to update key file {}: {}", path.display(), err);
+            }
+            false
+        }
+    }
+}
+
+/// Store the passcode key of an unlocked keybag in the key file.
+pub fn store(backup: &Backup, path: &Path) {
+    let (uuid, key) = match backup.get_keybag() {
+        Some(keybag) => match keybag.key {
+            Some(ref key) => (keybag.uuid, key.clone()),
+            None => return,
+        },
+        None => return,
+    };
+
+    let result = local_secret()
+        .map_err(BackupError::from)
+        .and_then(|secret| {
+            let mut keyfile = KeyFile::load(path)?;
+            keyfile.insert(uuid, &key, &secret)?;
+            keyfile.save(path)
+        });
+
+    match result {
+        Ok(_) => debug!("stored key for keybag {} in {}", uuid, path.display()),
+        Err(err) => warn!("failed to update key file {}: {}", path.display(), err),
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use ::plist::Value;
+use chrono::{DateTime, Utc};
+
+use crate::error::BackupError;
+use crate::util::unix_date;
+
+/// Set on files downloaded by an app, see `Quarantine`
+pub const QUARANTINE_ATTRIBUTE: &str = "com.apple.quarantine";
+
+/// The urls a file was downloaded from, a binary plist array of strings
+pub const WHERE_FROMS_ATTRIBUTE: &str = "com.apple.metadata:kMDItemWhereFroms";
+
+/// Decoded `com.apple.quarantine` attribute.
+/// The raw value looks like `0081;5e0d2c45;Safari;9A1B...`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quarantine {
+    /// Quarantine flags
+    pub flags: u32,
+
+    /// When the file was quarantined, in seconds since the unix epoch
+    pub timestamp: u64,
+
+    /// The app that downloaded the file
+    pub agent: String,
+
+    /// Identifier of the download event, if any
+    pub event_id: Option<String>,
+}
+
+impl Quarantine {
+    /// Parse the raw attribute value. Returns None if it is malformed.
+    pub fn parse(value: &[u8]) -> Option<Quarantine> {
+        let text = std::str::from_utf8(value).ok()?;
+        let text = text.trim_end_matches('\0');
+        let mut parts = text.split(';');
+
+        let flags = u32::from_str_radix(parts.next()?, 16).ok()?;
+        let timestamp = u64::from_str_radix(parts.next()?, 16).ok()?;
+        let agent = parts.next().unwrap_or("").to_string();
+        let event_id = parts
+            .next()
+            .filter(|id| !id.is_empty())
+            .map(|id| id.to_string());
+
+        Some(Quarantine {
+            flags,
+            timestamp,
+            agent,
+            event_id,
+        })
+    }
+
+    /// When the file was quarantined, or None if the timestamp is out of range.
+    pub fn date(&self) -> Option<DateTime<Utc>> {
+        unix_date(self.timestamp)
+    }
+}
+
+/// Parse the raw `kMDItemWhereFroms` attribute value. Returns None if it is malformed.
+pub fn parse_where_froms(value: &[u8]) -> Option<Vec<String>> {
+    match Value::from_reader(std::io::Cursor::new(value)).ok()? {
+        Value::Array(items) => Some(
+            items
+                .into_iter()
+                .filter_map(|item| item.into_string())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Decode the `ExtendedAttributes` of a file record, a binary plist of name -> data.
+pub fn decode_extended_attributes(data: &[u8]) -> Result<HashMap<String, Vec<u8>>, BackupError> {
+    let mut attributes = HashMap::new();
+
+    if let Value::Dictionary(dict) = Value::from_reader(std::io::Cursor::new(data))? {
+        for (name, value) in dict {
+            match value {
+                Value::Data(data) => {
+                    attributes.insert(name, data);
+                }
+                Value::String(text) => {
+                    attributes.insert(name, text.into_bytes());
+                }
+                other => warn!("skipping extended attribute {}: {:?}", name, other),
+            }
+        }
+    }
+
+    Ok(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantine() {
+        assert_eq!(
+            Quarantine::parse(b"0081;5e0d2c45;Safari;9A1B2C3D-0000-0000-0000-000000000000"),
+            Some(Quarantine {
+                flags: 0x81,
+                timestamp: 0x5e0d2c45,
+                agent: "Safari".to_string(),
+                event_id: Some("9A1B2C3D-0000-0000-0000-000000000000".to_string()),
+            })
+        );
+        assert_eq!(
+            Quarantine::parse(b"0001;5e0d2c45;;").map(|q| q.event_id),
+            Some(None)
+        );
+        assert_eq!(Quarantine::parse(b"garbage"), None);
+
+        let corrupt = Quarantine::parse(b"0001;ffffffffffff;Safari;").unwrap();
+        assert_eq!(corrupt.date(), None);
+    }
+
+    #[test]
+    fn test_where_froms() {
+        let mut data = vec![];
+        Value::Array(vec![
+            Value::String("https://example.com/file.zip".to_string()),
+            Value::String("https://example.com/".to_string()),
+        ])
+        .to_writer_binary(&mut data)
+        .unwrap();
+
+        assert_eq!(
+            parse_where_froms(&data),
+            Some(vec![
+                "https://example.com/file.zip".to_string(),
+                "https://example.com/".to_string()
+            ])
+        );
+        assert_eq!(parse_where_froms(b"not a plist"), None);
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use zip::ZipArchive;
+
+use crate::backup::reader::{open_archive_entry, ReadSeek, StoredEntryReader};
+use crate::error::BackupError;
+
+/// Where the files of a backup are stored: a directory, an archive, or memory.
+///
+/// Entries are named by their path inside of the storage, with `/` separators.
+/// The backup itself may be in a subdirectory, see `locate_root`.
+pub trait BackupBacking: fmt::Debug + Send + Sync {
+    /// Names of every file in the storage. Directories are not listed.
+    fn entries(&self) -> Result<Vec<String>, BackupError>;
+
+    /// Open a file for reading by its entry name.
+    /// Returns `InManifestButNotFound` if there is no such entry.
+    fn open_entry(&self, name: &str) -> Result<Box<dyn ReadSeek>, BackupError>;
+
+    /// Find the backup inside of the storage, as the directory containing `Manifest.plist`.
+    /// Returns an empty string if it is at the top level.
+    fn locate_root(&self) -> Result<String, BackupError> {
+        find_root(&self.entries()?)
+    }
+
+    /// The directory the storage lives in, if its files can be modified in place.
+    fn directory(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// The directory of the least nested `Manifest.plist` among the entry names.
+fn find_root(names: &[String]) -> Result<String, BackupError> {
+    names
+        .iter()
+        .filter(|name| name.as_str() == "Manifest.plist" || name.ends_with("/Manifest.plist"))
+        .min_by_key(|name| name.matches('/').count())
+        .map(|name| {
+            let dir = name.strip_suffix("Manifest.plist").unwrap_or(name);
+            dir.trim_end_matches('/').to_string()
+        })
+        .ok_or(BackupError::RootNotFound)
+}
+
+/// Open the storage at `path`, picking the backing from the file name:
+/// a zip file, a (compressed) tarball, or otherwise a directory.
+pub fn open_backing(path: &Path) -> Result<Box<dyn BackupBacking>, BackupError> {
+    if !path.is_file() {
+        return Ok(Box::new(DirectoryBacking::new(path)));
+    }
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if name.ends_with(".zip") {
+        Ok(Box::new(ZipBacking::open(path)?))
+    } else if let Some(compression) = TarCompression::from_name(&name) {
+        Ok(Box::new(TarBacking::open(path, compression)?))
+    } else {
+        Ok(Box::new(DirectoryBacking::new(path)))
+    }
+}
+
+/// A backup stored as a plain directory, like the ones iTunes and Finder write.
+#[derive(Debug)]
+pub struct DirectoryBacking {
+    path: PathBuf,
+}
+
+impl DirectoryBacking {
+    pub fn new<P: AsRef<Path>>(path: P) -> DirectoryBacking {
+        DirectoryBacking {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+/// Add every file below `dir` to `names`, prefixed with `prefix`.
+fn list_directory(dir: &Path, prefix: &str, names: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let kind = entry.file_type()?;
+
+        if kind.is_dir() {
+            list_directory(&entry.path(), &format!("{}/", name), names)?;
+        } else if kind.is_file() {
+            names.push(name);
+        }
+    }
+
+    Ok(())
+}
+
+impl BackupBacking for DirectoryBacking {
+    fn entries(&self) -> Result<Vec<String>, BackupError> {
+        let mut names = vec![];
+        list_directory(&self.path, "", &mut names)?;
+        Ok(names)
+    }
+
+    fn open_entry(&self, name: &str) -> Result<Box<dyn ReadSeek>, BackupError> {
+        let path = self.path.join(name);
+        if !path.is_file() {
+            return Err(BackupError::InManifestButNotFound);
+        }
+
+        Ok(Box::new(File::open(&path)?))
+    }
+
+    // a directory is the backup itself, searching below it could walk a whole disk
+    fn locate_root(&self) -> Result<String, BackupError> {
+        if self.path.join("Manifest.plist").is_file() {
+            Ok(String::new())
+        } else {
+            Err(BackupError::RootNotFound)
+        }
+    }
+
+    fn directory(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// A backup inside of a zip file.
+///
+/// The zip archive is only locked to look up an entry, every opened file reads
+/// from its own file handle, so files can be read from several threads at once.
+#[derive(Debug)]
+pub struct ZipBacking {
+    path: PathBuf,
+    archive: Mutex<ZipArchive<File>>,
+}
+
+impl ZipBacking {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ZipBacking, BackupError> {
+        let path = path.as_ref().to_path_buf();
+        let archive = ZipArchive::new(File::open(&path)?)?;
+
+        Ok(ZipBacking {
+            path,
+            archive: Mutex::new(archive),
+        })
+    }
+
+    fn archive(&self) -> std::sync::MutexGuard<'_, ZipArchive<File>> {
+        self.archive.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl BackupBacking for ZipBacking {
+    fn entries(&self) -> Result<Vec<String>, BackupError> {
+        Ok(self
+            .archive()
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .map(|name| name.to_string())
+            .collect())
+    }
+
+    fn open_entry(&self, name: &str) -> Result<Box<dyn ReadSeek>, BackupError> {
+        match open_archive_entry(&self.path, &mut self.archive(), name) {
+            Err(BackupError::Zip(zip::result::ZipError::FileNotFound)) => {
+                Err(BackupError::InManifestButNotFound)
+            }
+            result => result,
+        }
+    }
+}
+
+/// How a tarball is compressed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TarCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl TarCompression {
+    /// Guess the compression from a file name, or None if it isn't a tarball.
+    pub fn from_name(name: &str) -> Option<TarCompression> {
+        let name = name.to_lowercase();
+        if name.ends_with(".tar") {
+            Some(TarCompression::None)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(TarCompression::Gzip)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(TarCompression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// A backup inside of a tarball.
+///
+/// Entries of a plain tarball are read straight from the tar file. A compressed
+/// tarball can't be seeked into, so it is decompressed into a temporary file once
+/// when it is opened, which needs as much free space as the uncompressed backup.
+#[derive(Debug)]
+pub struct TarBacking {
+    /// The uncompressed tar file entries are read from
+    path: PathBuf,
+    /// Keeps the decompressed copy of a compressed tarball alive
+    _spool: Option<tempfile::NamedTempFile>,
+    /// Offset and size of the data of every regular file, by name
+    entries: HashMap<String, (u64, u64)>,
+}
+
+impl TarBacking {
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        compression: TarCompression,
+    ) -> Result<TarBacking, BackupError> {
+        let path = path.as_ref();
+
+        let (path, spool) = match compression {
+            TarCompression::None => (path.to_path_buf(), None),
+            TarCompression::Gzip | TarCompression::Zstd => {
+                debug!("decompressing {} to a temporary file", path.display());
+                let file = File::open(path)?;
+                let mut decoder: Box<dyn Read> = match compression {
+                    TarCompression::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+                    _ => Box::new(zstd::Decoder::new(file)?),
+                };
+
+                let mut spool = tempfile::NamedTempFile::new()?;
+                std::io::copy(&mut decoder, spool.as_file_mut())?;
+                (spool.path().to_path_buf(), Some(spool))
+            }
+        };
+
+        let entries = index_tar(File::open(&path)?)?;
+        debug!("indexed {} tar entries", entries.len());
+
+        Ok(TarBacking {
+            path,
+            _spool: spool,
+            entries,
+        })
+    }
+}
+
+/// Find the data offset and size of every regular file in a tar file.
+fn index_tar(file: File) -> Result<HashMap<String, (u64, u64)>, BackupError> {
+    let mut archive = tar::Archive::new(file);
+    let mut entries = HashMap::new();
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        match entry.header().entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => {}
+            _ => continue,
+        }
+
+        let name = entry.path()?.to_string_lossy().replace('\\', "/");
+        let name = name.trim_start_matches("./").to_string();
+        entries.insert(name, (entry.raw_file_position(), entry.size()));
+    }
+
+    Ok(entries)
+}
+
+impl BackupBacking for TarBacking {
+    fn entries(&self) -> Result<Vec<String>, BackupError> {
+        Ok(self.entries.keys().cloned().collect())
+    }
+
+    fn open_entry(&self, name: &str) -> Result<Box<dyn ReadSeek>, BackupError> {
+        let (start, len) = self
+            .entries
+            .get(name)
+            .ok_or(BackupError::InManifestButNotFound)?;
+
+        Ok(Box::new(StoredEntryReader::new(
+            File::open(&self.path)?,
+            *start,
+            *len,
+        )))
+    }
+}
+
+/// A backup held in memory, mostly for tests.
+#[derive(Debug, Default)]
+pub struct MemoryBacking {
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryBacking {
+    pub fn new() -> MemoryBacking {
+        MemoryBacking::default()
+    }
+
+    /// Add or replace a file.
+    pub fn insert<S: Into<String>>(&mut self, name: S, contents: Vec<u8>) {
+        self.files.insert(name.into(), contents);
+    }
+}
+
+impl BackupBacking for MemoryBacking {
+    fn entries(&self) -> Result<Vec<String>, BackupError> {
+        Ok(self.files.keys().cloned().collect())
+    }
+
+    fn open_entry(&self, name: &str) -> Result<Box<dyn ReadSeek>, BackupError> {
+        let contents = self
+            .files
+            .get(name)
+            .ok_or(BackupError::InManifestButNotFound)?;
+
+        Ok(Box::new(Cursor::new(contents.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom};
+
+    fn read_entry(backing: &dyn BackupBacking, name: &str) -> Vec<u8> {
+        let mut contents = vec![];
+        let mut reader = backing.open_entry(name).unwrap();
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_memory_backing() {
+        let mut backing = MemoryBacking::new();
+        backing.insert("backup/Manifest.plist", b"plist".to_vec());
+        backing.insert("backup/ab/abcdef", b"contents".to_vec());
+        backing.insert("other/Info.plist", vec![]);
+
+        assert_eq!(backing.locate_root().unwrap(), "backup");
+        assert_eq!(read_entry(&backing, "backup/ab/abcdef"), b"contents");
+        assert!(matches!(
+            backing.open_entry("backup/missing"),
+            Err(BackupError::InManifestButNotFound)
+        ));
+
+        assert!(matches!(
+            MemoryBacking::new().locate_root(),
+            Err(BackupError::RootNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_tar_backing() {
+        let mut builder = tar::Builder::new(vec![]);
+        for (name, contents) in &[
+            ("./Manifest.plist", &b"plist"[..]),
+            ("./ab/abcdef", &b"contents"[..]),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, *contents).unwrap();
+        }
+        let data = builder.into_inner().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("backup.tar");
+        std::fs::write(&plain, &data).unwrap();
+        let compressed = dir.path().join("backup.tar.zst");
+        std::fs::write(&compressed, zstd::encode_all(&data[..], 0).unwrap()).unwrap();
+
+        for path in &[plain, compressed] {
+            let backing = open_backing(path).unwrap();
+            let mut names = backing.entries().unwrap();
+            names.sort();
+
+            assert_eq!(names, vec!["Manifest.plist", "ab/abcdef"]);
+            assert_eq!(backing.locate_root().unwrap(), "");
+            assert_eq!(read_entry(backing.as_ref(), "ab/abcdef"), b"contents");
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::Path;
+
+use ::plist::Value;
+use rusqlite::{params, Connection, NO_PARAMS};
+
+use crate::backup::mbdb::strip_mbdb_encryption_keys;
+use crate::backup::{Backup, BackupFile, FileKind, ManifestFormat};
+use crate::error::BackupError;
+
+impl Backup {
+    /// Write a copy of this backup to `dest` as an unencrypted backup.
+    ///
+    /// Every file is decrypted with its unwrapped key, and the manifest is rewritten
+    /// without any keys: `Manifest.plist` has `IsEncrypted` set to false and no
+    /// `BackupKeyBag` or `ManifestKey`, and the file records have no `EncryptionKey`.
+    /// The manifest must be loaded first, see `unlock`.
+    ///
+    /// `progress` is called with every file and the number of bytes written for it.
+    /// A file that fails to decrypt doesn't stop the others from being written.
+    pub fn write_decrypted<F>(&self, dest: &Path, mut progress: F) -> Result<(), BackupError>
+    where
+        F: FnMut(&BackupFile, Result<u64, BackupError>),
+    {
+        std::fs::create_dir_all(dest)?;
+
+        for name in &["Info.plist", "Status.plist"] {
+            std::fs::write(dest.join(name), self.raw_file_read(name)?)?;
+        }
+
+        let mut manifest =
+            Value::from_reader(std::io::Cursor::new(self.raw_file_read("Manifest.plist")?))?;
+        if let Some(dict) = manifest.as_dictionary_mut() {
+            dict.insert("IsEncrypted".to_string(), Value::Boolean(false));
+            dict.remove("BackupKeyBag");
+            dict.remove("ManifestKey");
+        }
+        manifest.to_file_binary(dest.join("Manifest.plist"))?;
+
+        match self.format {
+            ManifestFormat::Mbdb => std::fs::write(
+                dest.join("Manifest.mbdb"),
+                strip_mbdb_encryption_keys(&self.raw_file_read("Manifest.mbdb")?)?,
+            )?,
+            ManifestFormat::Database => {
+                let manifest_db = if self.manifest.is_encrypted {
+                    self.decrypt_manifest_db()?
+                } else {
+                    self.raw_file_read("Manifest.db")?
+                };
+                let path = dest.join("Manifest.db");
+                std::fs::write(&path, manifest_db)?;
+                strip_file_keys(&path)?;
+            }
+        }
+
+        for file in &self.files {
+            if file.kind() != FileKind::File {
+                continue;
+            }
+
+            let result = self.write_decrypted_file(file, dest);
+            progress(file, result);
+        }
+
+        Ok(())
+    }
+
+    /// Decrypt a single file into the backup at `dest`, returning its size.
+    fn write_decrypted_file(&self, file: &BackupFile, dest: &Path) -> Result<u64, BackupError> {
+        let mut reader = self.open_file(file)?;
+
+        let path = dest.join(self.blob_path(file));
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut out = std::fs::File::create(&path)?;
+        Ok(std::io::copy(&mut reader, &mut out)?)
+    }
+}
+
+/// Remove the `EncryptionKey` from every archived file record in a decrypted `Manifest.db`.
+fn strip_file_keys(path: &Path) -> Result<(), BackupError> {
+    let mut conn = Connection::open(path)?;
+    let tx = conn.transaction()?;
+
+    let mut records = vec![];
+    {
+        let mut stmt = tx.prepare("SELECT fileID, file FROM Files")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+        for row in rows {
+            records.push(row?);
+        }
+    }
+
+    let mut stripped = 0;
+    for (fileid, record) in records {
+        let mut value = Value::from_reader(std::io::Cursor::new(record))?;
+        if !crate::plist::remove_archived_key(&mut value, "EncryptionKey")? {
+            continue;
+        }
+
+        let mut record = vec![];
+        value.to_writer_binary(&mut record)?;
+        tx.execute(
+            "UPDATE Files SET file = ?1 WHERE fileID = ?2",
+            params![record, fileid],
+        )?;
+        stripped += 1;
+    }
+
+    tx.commit()?;
+    debug!("removed {} file keys from the manifest", stripped);
+
+    Ok(())
+}
@@ -1,7 +1,18 @@
-use crate::lib::*;
+use crate::backup::attributes::{
+    decode_extended_attributes, parse_where_froms, Quarantine, QUARANTINE_ATTRIBUTE,
+    WHERE_FROMS_ATTRIBUTE,
+};
+use crate::backup::mode::{FileFlags, FileMode};
+use crate::backup::Backup;
+use crate::crypto::{KeyBag, ProtectionClass};
+use crate::error::BackupError;
+use crate::util::{as_u32_le, serialize_hex_map, unix_date};
 use ::plist::Value;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 /// Holds file metadata
 /// This corresponds to the `file` field of the manifest database
 pub struct FileInfo {
@@ -11,6 +22,9 @@ pub struct FileInfo {
     /// Last status change
     pub last_status_change: u64,
 
+    /// Last access, only recorded in `Manifest.mbdb` backups
+    pub last_accessed: Option<u64>,
+
     /// File creation date
     pub birth: u64,
 
@@ -35,38 +49,88 @@ pub struct FileInfo {
     /// File protection class used for key wrapping
     pub protection_class: ProtectionClass,
 
-    /// Wrapped version of the file encryption key
+    /// Wrapped version of the file encryption key, never serialized
+    #[serde(skip_serializing)]
     pub wrapped_encryption_key: Option<Vec<u8>>,
     pub wrapped_encryption_class: Option<ProtectionClass>,
 
-    /// Unwrapped encryption key
+    /// Unwrapped encryption key, never serialized
+    #[serde(skip)]
     pub encryption_key: Option<Vec<u8>>,
 
-    /// File extended attributes
-    pub extended_attributes: Option<Vec<u8>>,
+    /// File extended attributes, by name. Serialized as hex.
+    #[serde(serialize_with = "serialize_hex_map")]
+    pub extended_attributes: HashMap<String, Vec<u8>>,
+
+    /// Where a symlink points to, relative to the symlink or absolute on the device
+    pub target: Option<String>,
 }
 
 impl FileInfo {
-    pub fn unwrap_encryption_key(&mut self, keybag: &KeyBag) {
+    /// When the file was last modified, or None if the manifest doesn't record it
+    /// or it is out of range.
+    pub fn last_modified_date(&self) -> Option<DateTime<Utc>> {
+        Some(self.last_modified)
+            .filter(|&t| t != 0)
+            .and_then(unix_date)
+    }
+
+    /// When the file metadata last changed, or None if the manifest doesn't record it
+    /// or it is out of range.
+    pub fn last_status_change_date(&self) -> Option<DateTime<Utc>> {
+        Some(self.last_status_change)
+            .filter(|&t| t != 0)
+            .and_then(unix_date)
+    }
+
+    /// When the file was created, or None if the manifest doesn't record it
+    /// or it is out of range.
+    /// Manifest.mbdb backups have no creation date.
+    pub fn birth_date(&self) -> Option<DateTime<Utc>> {
+        Some(self.birth).filter(|&t| t != 0).and_then(unix_date)
+    }
+
+    /// The decoded file type and permission bits.
+    pub fn file_mode(&self) -> FileMode {
+        FileMode::from(self.mode)
+    }
+
+    /// The decoded BSD file flags.
+    pub fn file_flags(&self) -> FileFlags {
+        FileFlags(self.flags)
+    }
+
+    /// The `com.apple.quarantine` attribute, if the file has a valid one.
+    pub fn quarantine(&self) -> Option<Quarantine> {
+        Quarantine::parse(self.extended_attributes.get(QUARANTINE_ATTRIBUTE)?)
+    }
+
+    /// The urls the file was downloaded from, from `com.apple.metadata:kMDItemWhereFroms`.
+    pub fn where_froms(&self) -> Option<Vec<String>> {
+        parse_where_froms(self.extended_attributes.get(WHERE_FROMS_ATTRIBUTE)?)
+    }
+
+    pub fn unwrap_encryption_key(&mut self, keybag: &KeyBag) -> Result<(), BackupError> {
         // guard wrapped key
         let wrapped_encryption_key = match &self.wrapped_encryption_key {
             Some(el) => el,
-            _ => return,
+            _ => return Ok(()),
         };
 
         // guard class key
         let class_key = match keybag.find_class_key(&self.protection_class) {
             Some(class_key) => class_key,
-            _ => return,
+            _ => return Ok(()),
         };
 
-        let result_key =
-            crate::lib::crypto::unwrap_key(&class_key.as_slice(), wrapped_encryption_key);
+        let result_key = crate::crypto::unwrap_key(&class_key.as_slice(), wrapped_encryption_key)?;
         self.encryption_key = Some(result_key);
+
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct BackupFile {
     /// This corresponds to the hash of the file id on disk.
     /// fileid equals sha1(format!("{}-{}", domain, relative_filename))
@@ -85,35 +149,66 @@ pub struct BackupFile {
     pub fileinfo: Option<FileInfo>,
 }
 
+/// What kind of entry a file in the manifest is, from the manifest flags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    Unknown,
+}
+
+impl From<i64> for FileKind {
+    fn from(flags: i64) -> FileKind {
+        match flags {
+            1 => FileKind::File,
+            2 => FileKind::Directory,
+            4 => FileKind::Symlink,
+            _ => FileKind::Unknown,
+        }
+    }
+}
+
 impl BackupFile {
-    pub fn unwrap_file_key(&mut self, backup: &Backup) {
+    /// Whether this is a regular file, directory or symlink.
+    pub fn kind(&self) -> FileKind {
+        FileKind::from(self.flags)
+    }
+
+    /// Size of the file from the manifest, or 0 if it has no file info.
+    pub fn size(&self) -> u64 {
+        self.fileinfo.as_ref().map(|info| info.size).unwrap_or(0)
+    }
+
+    pub fn unwrap_file_key(&mut self, backup: &Backup) -> Result<(), BackupError> {
         let keybag = match backup.get_keybag() {
             Some(kb) => kb,
-            None => return,
+            None => return Ok(()),
         };
 
         match self.fileinfo.as_mut() {
-            Some(fileinfo) => {
-                fileinfo.unwrap_encryption_key(keybag);
-            }
-            None => {}
+            Some(fileinfo) => fileinfo.unwrap_encryption_key(keybag),
+            None => Ok(()),
         }
     }
 }
 
 use std::convert::TryFrom;
 impl TryFrom<::plist::Value> for FileInfo {
-    type Error = Box<dyn std::error::Error>;
+    type Error = BackupError;
 
     fn try_from(value: ::plist::Value) -> Result<FileInfo, Self::Error> {
         // First, decode as an NSKeyedArchiver archive.
-        let fork = crate::lib::plist::decode_nskeyedarchiver(value);
+        let fork = crate::plist::decode_nskeyedarchiver(value)?;
 
         if let Value::Dictionary(mut forkdict) = fork {
             // Unwrap contained binaryy data / attributes
             let val = forkdict.remove("EncryptionKey");
             if let Some(Value::Dictionary(dict)) = val {
                 if let Some(Value::Data(data)) = dict.get("NS.data") {
+                    if data.len() < 4 {
+                        return Err(BackupError::InvalidWrappedKey);
+                    }
                     let protclass = as_u32_le(&data[0..4]);
                     let mankey = &data[4..];
 
@@ -150,10 +245,19 @@ impl TryFrom<::plist::Value> for FileInfo {
                 None
             }
 
+            let extended_attributes = match read_data("ExtendedAttributes", &forkdict) {
+                Some(data) => decode_extended_attributes(&data).unwrap_or_else(|err| {
+                    warn!("failed to decode extended attributes: {}", err);
+                    HashMap::new()
+                }),
+                None => HashMap::new(),
+            };
+
             return Ok(FileInfo {
                 // modification times
                 last_modified: read_uint("LastModified", &forkdict).unwrap_or(0),
                 last_status_change: read_uint("LastStatusChange", &forkdict).unwrap_or(0),
+                last_accessed: None,
                 birth: read_uint("Birth", &forkdict).unwrap_or(0),
 
                 // filesystem info
@@ -173,10 +277,16 @@ impl TryFrom<::plist::Value> for FileInfo {
                 )),
                 wrapped_encryption_key: read_data("EncryptionKey", &forkdict),
                 encryption_key: None,
-                extended_attributes: read_data("ExtendedAttributes", &forkdict),
+                extended_attributes,
+                target: forkdict
+                    .get("Target")
+                    .and_then(|target| target.as_string())
+                    .map(|target| target.to_string()),
             });
         }
 
-        unimplemented!()
+        Err(BackupError::InvalidKeyedArchive(
+            "file record is not a dictionary",
+        ))
     }
 }
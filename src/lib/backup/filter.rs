@@ -0,0 +1,186 @@
+use glob::Pattern;
+use regex::Regex;
+
+use crate::backup::{BackupFile, FileKind};
+use crate::error::BackupError;
+
+/// Selects files from a backup manifest.
+///
+/// A file matches if it matches at least one of each kind of include rule that
+/// was given, and none of the exclude rules. An empty filter matches every file.
+///
+/// Domain and path patterns are globs, where `*` also matches `/`. Regexes are
+/// matched against `domain/relative_filename`.
+#[derive(Debug, Default, Clone)]
+pub struct FileFilter {
+    include_domains: Vec<Pattern>,
+    exclude_domains: Vec<Pattern>,
+    include_paths: Vec<Pattern>,
+    exclude_paths: Vec<Pattern>,
+    include_regexes: Vec<Regex>,
+    exclude_regexes: Vec<Regex>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    kinds: Vec<FileKind>,
+}
+
+fn parse_glob(pattern: &str) -> Result<Pattern, BackupError> {
+    Pattern::new(pattern).map_err(|err| BackupError::InvalidFilter(format!("{}: {}", pattern, err)))
+}
+
+fn parse_regex(pattern: &str) -> Result<Regex, BackupError> {
+    Regex::new(pattern).map_err(|err| BackupError::InvalidFilter(format!("{}: {}", pattern, err)))
+}
+
+impl FileFilter {
+    pub fn new() -> FileFilter {
+        FileFilter::default()
+    }
+
+    /// Only match files in domains matching this glob, e.g. `AppDomain-com.whatsapp*`.
+    pub fn include_domain(&mut self, pattern: &str) -> Result<(), BackupError> {
+        self.include_domains.push(parse_glob(pattern)?);
+        Ok(())
+    }
+
+    /// Skip files in domains matching this glob.
+    pub fn exclude_domain(&mut self, pattern: &str) -> Result<(), BackupError> {
+        self.exclude_domains.push(parse_glob(pattern)?);
+        Ok(())
+    }
+
+    /// Only match files with a relative path matching this glob, e.g. `*.sqlite`.
+    pub fn include_path(&mut self, pattern: &str) -> Result<(), BackupError> {
+        self.include_paths.push(parse_glob(pattern)?);
+        Ok(())
+    }
+
+    /// Skip files with a relative path matching this glob.
+    pub fn exclude_path(&mut self, pattern: &str) -> Result<(), BackupError> {
+        self.exclude_paths.push(parse_glob(pattern)?);
+        Ok(())
+    }
+
+    /// Only match files where `domain/relative_filename` matches this regex.
+    pub fn include_regex(&mut self, pattern: &str) -> Result<(), BackupError> {
+        self.include_regexes.push(parse_regex(pattern)?);
+        Ok(())
+    }
+
+    /// Skip files where `domain/relative_filename` matches this regex.
+    pub fn exclude_regex(&mut self, pattern: &str) -> Result<(), BackupError> {
+        self.exclude_regexes.push(parse_regex(pattern)?);
+        Ok(())
+    }
+
+    /// Only match files of at least this many bytes.
+    pub fn min_size(&mut self, size: u64) {
+        self.min_size = Some(size);
+    }
+
+    /// Only match files of at most this many bytes.
+    pub fn max_size(&mut self, size: u64) {
+        self.max_size = Some(size);
+    }
+
+    /// Only match files of this kind. Can be given more than once.
+    pub fn include_kind(&mut self, kind: FileKind) {
+        self.kinds.push(kind);
+    }
+
+    /// Check if a file matches the filter.
+    pub fn matches(&self, file: &BackupFile) -> bool {
+        let domain = file.domain.as_str();
+        let path = file.relative_filename.as_str();
+        let full_path = format!("{}/{}", domain, path);
+        let size = file.size();
+
+        (self.include_domains.is_empty() || self.include_domains.iter().any(|p| p.matches(domain)))
+            && !self.exclude_domains.iter().any(|p| p.matches(domain))
+            && (self.include_paths.is_empty() || self.include_paths.iter().any(|p| p.matches(path)))
+            && !self.exclude_paths.iter().any(|p| p.matches(path))
+            && (self.include_regexes.is_empty()
+                || self.include_regexes.iter().any(|r| r.is_match(&full_path)))
+            && !self.exclude_regexes.iter().any(|r| r.is_match(&full_path))
+            && !matches!(self.min_size, Some(min) if size < min)
+            && !matches!(self.max_size, Some(max) if size > max)
+            && (self.kinds.is_empty() || self.kinds.contains(&file.kind()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::FileInfo;
+    use crate::crypto::ProtectionClass;
+
+    fn file(domain: &str, path: &str, flags: i64, size: u64) -> BackupFile {
+        BackupFile {
+            fileid: String::new(),
+            domain: domain.to_string(),
+            relative_filename: path.to_string(),
+            flags,
+            fileinfo: Some(FileInfo {
+                last_modified: 0,
+                last_status_change: 0,
+                last_accessed: None,
+                birth: 0,
+                flags: 0,
+                inode: 0,
+                group_id: 0,
+                user_id: 0,
+                size,
+                mode: 0,
+                protection_class: ProtectionClass::from(0),
+                wrapped_encryption_key: None,
+                wrapped_encryption_class: None,
+                encryption_key: None,
+                extended_attributes: std::collections::HashMap::new(),
+                target: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let chat = file(
+            "AppDomain-com.whatsapp.WhatsApp",
+            "Documents/ChatStorage.sqlite",
+            1,
+            4096,
+        );
+        let sms = file("HomeDomain", "Library/SMS/sms.db", 1, 1024);
+        let dir = file("HomeDomain", "Library/SMS", 2, 0);
+
+        assert!(FileFilter::new().matches(&chat));
+
+        let mut filter = FileFilter::new();
+        filter.include_domain("AppDomain-com.whatsapp*").unwrap();
+        assert!(filter.matches(&chat));
+        assert!(!filter.matches(&sms));
+
+        let mut filter = FileFilter::new();
+        filter.include_path("*.sqlite").unwrap();
+        filter.include_path("*.db").unwrap();
+        assert!(filter.matches(&chat));
+        assert!(filter.matches(&sms));
+        filter.exclude_domain("Home*").unwrap();
+        assert!(!filter.matches(&sms));
+
+        let mut filter = FileFilter::new();
+        filter.include_regex("^HomeDomain/Library/SMS").unwrap();
+        filter.include_kind(FileKind::File);
+        assert!(filter.matches(&sms));
+        assert!(!filter.matches(&dir));
+
+        let mut filter = FileFilter::new();
+        filter.min_size(2000);
+        assert!(filter.matches(&chat));
+        assert!(!filter.matches(&sms));
+
+        assert!(matches!(
+            FileFilter::new().include_regex("("),
+            Err(BackupError::InvalidFilter(_))
+        ));
+    }
+}
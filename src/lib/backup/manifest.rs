@@ -1,19 +1,23 @@
 use log::debug;
 
-use crate::lib::*;
-use serde::Deserialize;
+use crate::crypto::{KeyBag, ProtectionClass};
+use crate::error::BackupError;
+use crate::util::as_u32_le;
+use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all(deserialize = "PascalCase"))]
 pub struct BackupManifest {
     pub is_encrypted: bool,
     pub version: String,
     pub date: String,
     pub system_domains_version: String,
     pub was_passcode_set: bool,
+    #[serde(skip_serializing)]
     pub manifest_key: Option<ByteBuf>,
     pub lockdown: BackupManifestLockdown,
+    #[serde(skip_serializing)]
     pub backup_key_bag: Option<ByteBuf>,
 
     #[serde(skip)]
@@ -23,8 +27,8 @@ pub struct BackupManifest {
     pub manifest_key_unwrapped: Option<Vec<u8>>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all(deserialize = "PascalCase"))]
 pub struct BackupManifestLockdown {
     pub product_version: String,
     pub product_type: String,
@@ -38,10 +42,13 @@ pub struct BackupManifestLockdown {
 impl BackupManifest {
     /// Unwrap manifest key using protection class
     /// https://stackoverflow.com/questions/1498342/how-to-decrypt-an-encrypted-apple-itunes-iphone-backup/13793043
-    pub fn unlock_manifest(&mut self) {
+    pub fn unlock_manifest(&mut self) -> Result<(), BackupError> {
         if let Some(ref manifest_key) = self.manifest_key {
             debug!("unwrapping manifest key...");
             let sliced: &Vec<u8> = manifest_key.as_ref();
+            if sliced.len() < 4 {
+                return Err(BackupError::InvalidWrappedKey);
+            }
             let protclass = as_u32_le(&sliced[0..4]);
             let mankey = &sliced[4..];
             debug!("manifest protection class: {:x?}", protclass);
@@ -49,14 +56,16 @@ impl BackupManifest {
             let class_key = self
                 .keybag
                 .as_ref()
-                .expect("expect locked manifest to have keybag")
+                .ok_or(BackupError::NoKeybag)?
                 .find_class_key(&clazz)
-                .unwrap();
+                .ok_or(BackupError::NoClassKey(clazz))?;
             let items: Vec<u8> = mankey.iter().cloned().collect();
-            let result_key = crate::lib::crypto::unwrap_key(&class_key, &items);
+            let result_key = crate::crypto::unwrap_key(&class_key, &items)?;
             self.manifest_key_unwrapped = Some(result_key);
             trace!("unwrapped manifest key: {:x?}", self.manifest_key_unwrapped);
             debug!("unwrapped manifest key successfully!");
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,373 @@
+use crate::backup::{BackupFile, FileInfo};
+use crate::crypto::ProtectionClass;
+use crate::error::BackupError;
+use crate::util::as_u32_le;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+/// Every `Manifest.mbdb` starts with this magic, followed by the format version (5.0)
+const MBDB_HEADER: &[u8] = b"mbdb\x05\x00";
+
+/// Strings in the mbdb are prefixed with a u16 length, 0xFFFF marks an empty value
+const MBDB_EMPTY_STRING: u16 = 0xFFFF;
+
+/// Unix file type bits, used to recover the manifest flags from the mode
+const S_IFMT: u64 = 0o170000;
+const S_IFDIR: u64 = 0o040000;
+const S_IFLNK: u64 = 0o120000;
+
+fn read_u8(cur: &mut Cursor<&[u8]>) -> std::io::Result<u8> {
+    let mut buf = [0u8; 1];
+    cur.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(cur: &mut Cursor<&[u8]>) -> std::io::Result<u16> {
+    let mut buf = [0u8; 2];
+    cur.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+fn read_u32(cur: &mut Cursor<&[u8]>) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    cur.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64(cur: &mut Cursor<&[u8]>) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8];
+    cur.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+/// Read a length-prefixed mbdb string as raw bytes.
+fn read_data(cur: &mut Cursor<&[u8]>) -> std::io::Result<Option<Vec<u8>>> {
+    let length = read_u16(cur)?;
+    if length == MBDB_EMPTY_STRING {
+        return Ok(None);
+    }
+
+    let mut buf = vec![0u8; length as usize];
+    cur.read_exact(&mut buf)?;
+    Ok(Some(buf))
+}
+
+/// Read a length-prefixed mbdb string as utf-8 text.
+fn read_string(cur: &mut Cursor<&[u8]>) -> std::io::Result<String> {
+    Ok(read_data(cur)?
+        .map(|data| String::from_utf8_lossy(&data).to_string())
+        .unwrap_or_default())
+}
+
+/// Compute the on-disk file id for a domain and path.
+/// fileid equals sha1(format!("{}-{}", domain, relative_filename))
+pub fn compute_fileid(domain: &str, relative_filename: &str) -> String {
+    let digest = ring::digest::digest(
+        &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+        format!("{}-{}", domain, relative_filename).as_bytes(),
+    );
+    hex::encode(digest.as_ref())
+}
+
+/// Parse a single record of the mbdb into a BackupFile.
+fn read_record(cur: &mut Cursor<&[u8]>) -> std::io::Result<BackupFile> {
+    let domain = read_string(cur)?;
+    let relative_filename = read_string(cur)?;
+    let link_target = read_string(cur)?;
+    let _data_hash = read_data(cur)?;
+    let encryption_key = read_data(cur)?;
+    let mode = read_u16(cur)? as u64;
+    let inode = read_u64(cur)?;
+    let user_id = read_u32(cur)? as u64;
+    let group_id = read_u32(cur)? as u64;
+    let last_modified = read_u32(cur)? as u64;
+    let last_accessed = read_u32(cur)? as u64;
+    let last_status_change = read_u32(cur)? as u64;
+    let size = read_u64(cur)?;
+    let protection_class = read_u8(cur)?;
+    let property_count = read_u8(cur)?;
+
+    // Properties are the file's extended attributes
+    let mut extended_attributes = HashMap::new();
+    for _ in 0..property_count {
+        let name = read_string(cur)?;
+        let value = read_data(cur)?.unwrap_or_default();
+        extended_attributes.insert(name, value);
+    }
+
+    // The key blob matches the Manifest.db layout: a 4-byte little endian
+    // protection class followed by the wrapped key.
+    let (wrapped_encryption_class, wrapped_encryption_key) = match encryption_key {
+        Some(ref key) if key.len() > 4 => (
+            Some(ProtectionClass::from(as_u32_le(&key[0..4]))),
+            Some(key[4..].to_vec()),
+        ),
+        _ => (None, None),
+    };
+
+    let flags = match mode & S_IFMT {
+        S_IFDIR => 2,
+        S_IFLNK => 4,
+        _ => 1,
+    };
+
+    Ok(BackupFile {
+        fileid: compute_fileid(&domain, &relative_filename),
+        domain,
+        relative_filename,
+        flags,
+        fileinfo: Some(FileInfo {
+            last_modified,
+            last_status_change,
+            last_accessed: Some(last_accessed),
+            birth: 0,
+            flags: 0,
+            inode,
+            group_id,
+            user_id,
+            size,
+            mode,
+            protection_class: ProtectionClass::from(protection_class as u32),
+            wrapped_encryption_key,
+            wrapped_encryption_class,
+            encryption_key: None,
+            extended_attributes,
+            target: if link_target.is_empty() {
+                None
+            } else {
+                Some(link_target)
+            },
+        }),
+    })
+}
+
+/// Parse the contents of a legacy (iOS 5 - 9) Manifest.mbdb file.
+pub fn parse_mbdb(data: &[u8]) -> Result<Vec<BackupFile>, BackupError> {
+    if !data.starts_with(MBDB_HEADER) {
+        return Err(BackupError::InvalidMbdbHeader);
+    }
+
+    let mut cur = Cursor::new(data);
+    cur.set_position(MBDB_HEADER.len() as u64);
+
+    let mut files = vec![];
+    while (cur.position() as usize) < data.len() {
+        let offset = cur.position() as usize;
+        match read_record(&mut cur) {
+            Ok(file) => files.push(file),
+            Err(_) => return Err(BackupError::MalformedMbdb { offset }),
+        }
+    }
+
+    debug!("read {} records from mbdb", files.len());
+
+    Ok(files)
+}
+
+/// Copy a single record to `out`, with its encryption key left empty.
+fn strip_record(cur: &mut Cursor<&[u8]>, out: &mut Vec<u8>) -> std::io::Result<()> {
+    let data = *cur.get_ref();
+
+    // domain, path, link target and data hash are kept as is
+    let start = cur.position() as usize;
+    for _ in 0..4 {
+        read_data(cur)?;
+    }
+    out.extend_from_slice(&data[start..cur.position() as usize]);
+
+    read_data(cur)?;
+    out.extend_from_slice(&MBDB_EMPTY_STRING.to_be_bytes());
+
+    // mode through protection class are fixed size, followed by the properties
+    let rest = cur.position() as usize;
+    cur.set_position(cur.position() + 2 + 8 + 4 * 5 + 8 + 1);
+    let property_count = read_u8(cur)?;
+    for _ in 0..property_count as usize * 2 {
+        read_data(cur)?;
+    }
+    out.extend_from_slice(&data[rest..cur.position() as usize]);
+
+    Ok(())
+}
+
+/// Copy a Manifest.mbdb with the encryption key of every record removed,
+/// as in the manifest of an unencrypted backup.
+pub fn strip_mbdb_encryption_keys(data: &[u8]) -> Result<Vec<u8>, BackupError> {
+    if !data.starts_with(MBDB_HEADER) {
+        return Err(BackupError::InvalidMbdbHeader);
+    }
+
+    let mut cur = Cursor::new(data);
+    cur.set_position(MBDB_HEADER.len() as u64);
+
+    let mut out = MBDB_HEADER.to_vec();
+    while (cur.position() as usize) < data.len() {
+        let offset = cur.position() as usize;
+        if strip_record(&mut cur, &mut out).is_err() {
+            return Err(BackupError::MalformedMbdb { offset });
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::FileKind;
+
+    fn push_string(out: &mut Vec<u8>, value: Option<&[u8]>) {
+        match value {
+            Some(value) => {
+                out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+                out.extend_from_slice(value);
+            }
+            None => out.extend_from_slice(&MBDB_EMPTY_STRING.to_be_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_fileid() {
+        assert_eq!(
+            compute_fileid("HomeDomain", "Library/SMS/sms.db"),
+            "3d0d7e5fb2ce288813306e4d4636395e047a3d28"
+        );
+    }
+
+    #[test]
+    fn test_parse_record() {
+        let mut data = MBDB_HEADER.to_vec();
+        push_string(&mut data, Some(b"HomeDomain"));
+        push_string(&mut data, Some(b"Library/SMS/sms.db"));
+        push_string(&mut data, None);
+        push_string(&mut data, None);
+        push_string(&mut data, Some(&[3, 0, 0, 0, 0xAA, 0xBB, 0xCC, 0xDD]));
+        data.extend_from_slice(&0o100644u16.to_be_bytes());
+        data.extend_from_slice(&42u64.to_be_bytes());
+        data.extend_from_slice(&501u32.to_be_bytes());
+        data.extend_from_slice(&501u32.to_be_bytes());
+        data.extend_from_slice(&1_400_000_000u32.to_be_bytes());
+        data.extend_from_slice(&1_400_000_001u32.to_be_bytes());
+        data.extend_from_slice(&1_400_000_002u32.to_be_bytes());
+        data.extend_from_slice(&1234u64.to_be_bytes());
+        data.push(3);
+        data.push(0);
+
+        let files = parse_mbdb(&data).unwrap();
+        assert_eq!(files.len(), 1);
+
+        let file = &files[0];
+        assert_eq!(file.fileid, "3d0d7e5fb2ce288813306e4d4636395e047a3d28");
+        assert_eq!(file.flags, 1);
+
+        let info = file.fileinfo.as_ref().unwrap();
+        assert_eq!(info.size, 1234);
+        assert_eq!(info.inode, 42);
+        assert_eq!(info.last_modified, 1_400_000_000);
+        assert_eq!(info.last_accessed, Some(1_400_000_001));
+        assert_eq!(
+            info.protection_class,
+            ProtectionClass::NSFileProtectionCompleteUntilFirstUserAuthentication
+        );
+        assert_eq!(
+            info.wrapped_encryption_key,
+            Some(vec![0xAA, 0xBB, 0xCC, 0xDD])
+        );
+    }
+
+    #[test]
+    fn test_properties() {
+        let mut data = MBDB_HEADER.to_vec();
+        push_string(&mut data, Some(b"MediaDomain"));
+        push_string(&mut data, Some(b"Media/file.txt"));
+        for _ in 0..3 {
+            push_string(&mut data, None);
+        }
+        data.extend_from_slice(&0o100644u16.to_be_bytes());
+        data.extend_from_slice(&[0u8; 8 + 4 * 5 + 8]);
+        data.push(0);
+        data.push(1);
+        push_string(&mut data, Some(b"com.apple.quarantine"));
+        push_string(&mut data, Some(b"0081;5e0d;Safari;"));
+
+        let files = parse_mbdb(&data).unwrap();
+        let info = files[0].fileinfo.as_ref().unwrap();
+        assert_eq!(
+            info.extended_attributes
+                .get("com.apple.quarantine")
+                .map(|v| v.as_slice()),
+            Some(&b"0081;5e0d;Safari;"[..])
+        );
+        assert_eq!(info.quarantine().unwrap().agent, "Safari");
+    }
+
+    #[test]
+    fn test_symlink() {
+        let mut data = MBDB_HEADER.to_vec();
+        push_string(&mut data, Some(b"HomeDomain"));
+        push_string(&mut data, Some(b"Library/link"));
+        push_string(&mut data, Some(b"SMS/sms.db"));
+        push_string(&mut data, None);
+        push_string(&mut data, None);
+        data.extend_from_slice(&0o120755u16.to_be_bytes());
+        data.extend_from_slice(&[0u8; 8 + 4 * 5 + 8]);
+        data.push(0);
+        data.push(0);
+
+        let files = parse_mbdb(&data).unwrap();
+        assert_eq!(files[0].kind(), FileKind::Symlink);
+        assert_eq!(
+            files[0].fileinfo.as_ref().unwrap().target.as_deref(),
+            Some("SMS/sms.db")
+        );
+    }
+
+    #[test]
+    fn test_strip_encryption_keys() {
+        let mut data = MBDB_HEADER.to_vec();
+        push_string(&mut data, Some(b"HomeDomain"));
+        push_string(&mut data, Some(b"Library/SMS/sms.db"));
+        push_string(&mut data, None);
+        push_string(&mut data, None);
+        push_string(&mut data, Some(&[3, 0, 0, 0, 0xAA, 0xBB, 0xCC, 0xDD]));
+        data.extend_from_slice(&0o100644u16.to_be_bytes());
+        data.extend_from_slice(&[0u8; 8 + 4 * 5]);
+        data.extend_from_slice(&1234u64.to_be_bytes());
+        data.push(3);
+        data.push(1);
+        push_string(&mut data, Some(b"com.apple.quarantine"));
+        push_string(&mut data, Some(b"0081;5e0d;Safari;"));
+
+        let stripped = strip_mbdb_encryption_keys(&data).unwrap();
+        assert_eq!(stripped.len(), data.len() - 8);
+
+        let files = parse_mbdb(&stripped).unwrap();
+        let info = files[0].fileinfo.as_ref().unwrap();
+        assert_eq!(info.wrapped_encryption_key, None);
+        assert_eq!(info.size, 1234);
+        assert_eq!(info.quarantine().unwrap().agent, "Safari");
+
+        assert!(matches!(
+            strip_mbdb_encryption_keys(&data[..data.len() - 1]),
+            Err(BackupError::MalformedMbdb { offset: 6 })
+        ));
+    }
+
+    #[test]
+    fn test_bad_header() {
+        assert!(matches!(
+            parse_mbdb(b"mbdx\x02\x00"),
+            Err(BackupError::InvalidMbdbHeader)
+        ));
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let mut data = MBDB_HEADER.to_vec();
+        push_string(&mut data, Some(b"HomeDomain"));
+        assert!(matches!(
+            parse_mbdb(&data),
+            Err(BackupError::MalformedMbdb { offset: 6 })
+        ));
+    }
+}
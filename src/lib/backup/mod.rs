@@ -1,130 +1,209 @@
+mod attributes;
+mod backing;
+mod decrypt;
 mod file;
+mod filter;
 mod info;
 mod manifest;
+mod mbdb;
+mod mode;
+mod reader;
 mod status;
-
-use crate::lib::crypto::*;
-pub use file::{BackupFile, FileInfo};
+mod verify;
+
+use crate::crypto::*;
+use crate::error::BackupError;
+pub use attributes::{Quarantine, QUARANTINE_ATTRIBUTE, WHERE_FROMS_ATTRIBUTE};
+pub use backing::{
+    open_backing, BackupBacking, DirectoryBacking, MemoryBacking, TarBacking, TarCompression,
+    ZipBacking,
+};
+pub use file::{BackupFile, FileInfo, FileKind};
+pub use filter::FileFilter;
 pub use info::BackupInfo;
 pub use manifest::{BackupManifest, BackupManifestLockdown};
+pub use mbdb::parse_mbdb;
+pub use mode::{Access, FileFlags, FileMode};
+pub use reader::ReadSeek;
 pub use status::BackupStatus;
+pub use verify::{VerifyIssue, VerifyProblem, VerifyReport};
 
 use std::convert::TryFrom;
 use std::io::Read;
-use std::path::{Path};
+use std::path::{Path, PathBuf};
 
 use rusqlite::OpenFlags;
 use rusqlite::{Connection, NO_PARAMS};
 
-use std::cell::RefCell;
-use zip::{self, ZipArchive};
-
-/// Stores where this backup is physcially stored.
-/// Internal retreival can change depending on if this an actual file or a zip file
-#[derive(Debug)]
-pub enum BackupBacking {
-    Filesystem,
-    ZipFile(RefCell<ZipArchive<std::fs::File>>),
+/// Which format the backup's file listing is stored in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManifestFormat {
+    /// iOS 10+ sqlite database, `Manifest.db`
+    Database,
+    /// iOS 5 - 9 flat binary listing, `Manifest.mbdb`
+    Mbdb,
 }
 
 #[derive(Debug)]
-pub struct Backup<'a> {
-    pub path: Box<&'a Path>,
+pub struct Backup {
+    pub path: PathBuf,
     pub manifest: BackupManifest,
     pub info: BackupInfo,
     pub status: BackupStatus,
     pub files: Vec<BackupFile>,
+    /// Where the backup is inside of its backing, None if it is at the top level
     pub relative_root: Option<String>,
-    pub backing: BackupBacking,
+    pub backing: Box<dyn BackupBacking>,
+    pub format: ManifestFormat,
 }
 
-fn read_archive_file(
-    archive: &mut zip::ZipArchive<std::fs::File>,
-    path: &str,
-) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let mut res: Vec<u8> = vec![];
-    archive.by_name(path)?.read_to_end(&mut res)?;
+impl Backup {
+    /// Open a backup from its root path, which is a directory, a zip file or a tarball.
+    /// Encrypted backups need to be unlocked before their files can be read.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Backup, BackupError> {
+        Backup::new(path.as_ref())
+    }
 
-    Ok(res)
-}
+    /// Unlock the backup with its password, and load the manifest.
+    /// The password is ignored if the backup isn't encrypted.
+    pub fn unlock(mut self, password: &str) -> Result<Backup, BackupError> {
+        if self.manifest.is_encrypted {
+            self.parse_keybag()?;
+            self.unlock_keybag(password)?;
+        }
 
-impl Backup<'_> {
-    /// Create from root backup path.
-    pub fn new(path: &Path) -> Result<Backup, Box<dyn std::error::Error>> {
-        let status;
-        let manifest;
-        let info;
-        let mut relative_root: Option<String> = None;
-        let mut backing = BackupBacking::Filesystem;
-
-        if path.is_file() && path.extension().and_then(std::ffi::OsStr::to_str) == Some("zip") {
-            let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
-
-            let names = archive
-                .file_names()
-                .map(|v| v.to_string())
-                .collect::<Vec<String>>();
-            let mut root_path: Option<String> = None;
-
-            for name in names {
-                if name.ends_with("Manifest.plist") {
-                    println!("{}", name);
-                    let path = Path::new(&name);
-                    root_path = path
-                        .parent()
-                        .and_then(|v| v.as_os_str().to_str().and_then(|v| Some(v.to_string())));
-                    debug!("found zip root: {:?}", path.parent());
-                    break;
-                }
-            }
+        self.load_manifest()?;
+
+        Ok(self)
+    }
+
+    /// Load the manifest, and unwrap every file key if the backup is encrypted.
+    /// Encrypted backups must be unlocked with `unlock_keybag` first.
+    pub fn load_manifest(&mut self) -> Result<(), BackupError> {
+        self.parse_manifest()?;
+        self.unwrap_file_keys()
+    }
 
-            let zip_root = match root_path {
-            Some(v) => v,
-            None => panic!("could not find the Manifest.plist inside the zip file. Is this actually a backup?")
-          };
-
-            status = plist::from_bytes(&read_archive_file(
-                &mut archive,
-                &format!("{}/Status.plist", &zip_root),
-            )?)?;
-            info = plist::from_bytes(&read_archive_file(
-                &mut archive,
-                &format!("{}/Info.plist", &zip_root),
-            )?)?;
-            manifest = plist::from_bytes(&read_archive_file(
-                &mut archive,
-                &format!("{}/Manifest.plist", &zip_root),
-            )?)?;
-
-            // init ctrl vars
-            relative_root = Some(zip_root);
-            backing = BackupBacking::ZipFile(RefCell::new(archive));
+    /// The files listed in the manifest, once it has been loaded.
+    pub fn files(&self) -> &[BackupFile] {
+        &self.files
+    }
+
+    /// Create from root backup path, see `open_backing` for the supported formats.
+    pub fn new(path: &Path) -> Result<Backup, BackupError> {
+        Backup::with_backing(path, open_backing(path)?)
+    }
+
+    /// Create from a backing, e.g. a `MemoryBacking`. `path` is only used to name the backup.
+    pub fn with_backing<P: AsRef<Path>>(
+        path: P,
+        backing: Box<dyn BackupBacking>,
+    ) -> Result<Backup, BackupError> {
+        let root = backing.locate_root()?;
+        debug!("found backup root: {:?}", root);
+        let relative_root = Some(root).filter(|root| !root.is_empty());
+
+        let read = |name: &str| -> Result<Vec<u8>, BackupError> {
+            let mut contents = vec![];
+            backing
+                .open_entry(&entry_name(&relative_root, name))?
+                .read_to_end(&mut contents)?;
+            Ok(contents)
+        };
+
+        let status = plist::from_bytes(&read("Status.plist")?)?;
+        let info = plist::from_bytes(&read("Info.plist")?)?;
+        let manifest = plist::from_bytes(&read("Manifest.plist")?)?;
+
+        let exists = |name: &str| {
+            backing
+                .open_entry(&entry_name(&relative_root, name))
+                .is_ok()
+        };
+        let format = if !exists("Manifest.db") && exists("Manifest.mbdb") {
+            ManifestFormat::Mbdb
         } else {
-            status = plist::from_file(format!("{}/Status.plist", path.to_str().unwrap()))?;
-            info = plist::from_file(format!("{}/Info.plist", path.to_str().unwrap()))?;
-            manifest = plist::from_file(format!("{}/Manifest.plist", path.to_str().unwrap()))?;
-        }
+            ManifestFormat::Database
+        };
+
+        debug!("backup manifest format: {:?}", format);
 
         Ok(Backup {
-            path: Box::new(path.clone()),
+            path: path.as_ref().to_path_buf(),
             manifest,
             status,
             info,
             relative_root,
             files: vec![],
             backing,
+            format,
         })
     }
 
     /// Parse the keybag contained in the manifest.
-    pub fn parse_keybag(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn parse_keybag(&mut self) -> Result<(), BackupError> {
         if let Some(bag) = &self.manifest.backup_key_bag {
-            self.manifest.keybag = Some(KeyBag::init(bag.to_vec()));
+            self.manifest.keybag = Some(KeyBag::init(bag.to_vec())?);
         }
 
         Ok(())
     }
 
+    /// Unlock the keybag with the backup password, and unwrap the manifest key.
+    /// The keybag must have been parsed with `parse_keybag` first.
+    pub fn unlock_keybag(&mut self, password: &str) -> Result<(), BackupError> {
+        if let Some(keybag) = self.manifest.keybag.as_mut() {
+            keybag.unlock_with_passcode(password)?;
+        }
+
+        self.manifest.unlock_manifest()
+    }
+
+    /// Unlock the keybag with an already derived passcode key, and unwrap the manifest key.
+    /// This skips the slow key derivation, see `KeyBag::derive_passcode_key`.
+    pub fn unlock_keybag_with_key(&mut self, passcode_key: Vec<u8>) -> Result<(), BackupError> {
+        if let Some(keybag) = self.manifest.keybag.as_mut() {
+            keybag.unlock_with_key(passcode_key)?;
+        }
+
+        self.manifest.unlock_manifest()
+    }
+
+    /// Re-protect the backup with a new password, and save the new keybag to `Manifest.plist`.
+    /// The keybag must be unlocked first. File keys and contents are left as they are,
+    /// since the class keys they are wrapped with don't change.
+    /// See `KeyBag::set_passcode` for `iterations` and `dpic`.
+    pub fn change_password(
+        &mut self,
+        password: &str,
+        iterations: Option<u32>,
+        dpic: Option<u32>,
+    ) -> Result<(), BackupError> {
+        let dir = match self.backing.directory() {
+            Some(dir) => dir.to_path_buf(),
+            None => return Err(BackupError::ReadOnlyBacking),
+        };
+
+        let keybag = self.manifest.keybag.as_mut().ok_or(BackupError::NoKeybag)?;
+        keybag.set_passcode(password, iterations, dpic)?;
+        let data = keybag.to_bytes()?;
+
+        let path = dir.join("Manifest.plist");
+        let mut manifest = plist::Value::from_file(&path)?;
+        if let Some(dict) = manifest.as_dictionary_mut() {
+            dict.insert("BackupKeyBag".to_string(), plist::Value::Data(data.clone()));
+        }
+
+        // replace the manifest in one step, so a failed write can't lose the keybag
+        let tmp = dir.join("Manifest.plist.tmp");
+        manifest.to_file_binary(&tmp)?;
+        std::fs::rename(&tmp, &path)?;
+
+        self.manifest.backup_key_bag = Some(serde_bytes::ByteBuf::from(data));
+
+        Ok(())
+    }
+
     pub fn get_keybag(&self) -> Option<&KeyBag> {
         match &self.manifest.keybag {
             Some(kb) => Some(kb),
@@ -154,68 +233,90 @@ impl Backup<'_> {
         return None;
     }
 
-    pub fn raw_file_read(&self, path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        match &self.backing {
-            BackupBacking::Filesystem => {
-                // prepend fs path
-                let finpath = self.path.join(Path::new(&path));
-                if !finpath.is_file() {
-                    return Err(crate::lib::error::BackupError::InManifestButNotFound.into());
-                }
+    /// Find every file in the manifest matching a filter.
+    pub fn find_files(&self, filter: &FileFilter) -> Vec<&BackupFile> {
+        self.files
+            .iter()
+            .filter(|file| filter.matches(file))
+            .collect()
+    }
 
-                Ok(std::fs::read(&finpath)?)
-            }
-            BackupBacking::ZipFile(archive) => read_archive_file(
-                &mut archive.borrow_mut(),
-                &match &self.relative_root {
-                    Some(rootpath) => format!("{}/{}", rootpath, path),
-                    None => path.to_string(),
-                },
-            ),
+    /// Open a file in the backup by its path relative to the backup root.
+    pub fn raw_file_open(&self, path: &str) -> Result<Box<dyn ReadSeek>, BackupError> {
+        self.backing
+            .open_entry(&entry_name(&self.relative_root, path))
+    }
+
+    pub fn raw_file_read(&self, path: &str) -> Result<Vec<u8>, BackupError> {
+        let mut contents = vec![];
+        self.raw_file_open(path)?.read_to_end(&mut contents)?;
+
+        Ok(contents)
+    }
+
+    /// Path of a file's contents, relative to the backup root.
+    fn blob_path(&self, file: &BackupFile) -> String {
+        match self.format {
+            ManifestFormat::Database => format!("{}/{}", &file.fileid[0..2], file.fileid),
+            // legacy backups store every file flat in the backup root
+            ManifestFormat::Mbdb => file.fileid.to_string(),
         }
     }
 
-    #[allow(dead_code)]
-    pub fn read_file(&self, file: &BackupFile) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let path = format!(
-            "{}/{}/{}",
-            self.path.to_str().expect("path to be str"),
-            (&file.fileid)[0..2].to_string(),
-            file.fileid
-        );
+    /// Open a file for reading, decrypting it as it is read if the backup is encrypted.
+    /// For encrypted backups, the file key must be unwrapped first, and the
+    /// contents are cut to the file's size in the manifest.
+    pub fn open_file(&self, file: &BackupFile) -> Result<Box<dyn ReadSeek>, BackupError> {
+        let path = self.blob_path(file);
+        debug!("open backup file path: {}", path);
 
-        debug!("read backup file path: {}", path);
+        let contents = self.raw_file_open(&path)?;
 
-        let contents = self.raw_file_read(&path)?;
+        if !self.manifest.is_encrypted {
+            return Ok(contents);
+        }
 
-        // if the file
-        if self.manifest.is_encrypted {
-            debug!("file {} is encrypted, decrypting...", path);
-            match &file.fileinfo.as_ref() {
-                Some(fileinfo) => match fileinfo.encryption_key.as_ref() {
-                    Some(encryption_key) => {
-                        let dec = crate::lib::crypto::decrypt_with_key(&encryption_key, &contents);
-                        debug!("file {} is now decrypted...", path);
-                        return Ok(dec);
-                    }
-                    None => {
-                        return Err(crate::lib::error::BackupError::NoEncryptionKey.into());
-                    }
-                },
-                None => {
-                    return Err(crate::lib::error::BackupError::NoFileInfo.into());
-                }
+        debug!("file {} is encrypted, decrypting as it is read...", path);
+        let fileinfo = file.fileinfo.as_ref().ok_or(BackupError::NoFileInfo)?;
+        let encryption_key = fileinfo
+            .encryption_key
+            .as_ref()
+            .ok_or(BackupError::NoEncryptionKey)?;
+
+        let mut reader = DecryptReader::new(contents, encryption_key)?;
+
+        // Cut the plaintext to the size in the manifest, which removes the padding.
+        // The padding must agree with it, otherwise the blob doesn't belong to this record.
+        let expected = fileinfo.size;
+        match reader.pkcs7_len()? {
+            Some(actual) if actual != expected => {
+                return Err(BackupError::SizeMismatch { expected, actual })
             }
+            Some(_) => {}
+            None if expected > reader.padded_len() => {
+                return Err(BackupError::SizeMismatch {
+                    expected,
+                    actual: reader.padded_len(),
+                })
+            }
+            None => warn!("file {} has invalid padding, using manifest size", path),
         }
+        reader.set_len(expected);
 
-        return match std::fs::read(Path::new(&path)) {
-            Ok(vec) => Ok(vec),
-            Err(err) => Err(err.into()),
-        };
+        Ok(Box::new(reader))
+    }
+
+    /// Read the full contents of a file into memory.
+    /// Prefer `open_file` for large files.
+    pub fn read_file(&self, file: &BackupFile) -> Result<Vec<u8>, BackupError> {
+        let mut contents = vec![];
+        self.open_file(file)?.read_to_end(&mut contents)?;
+
+        Ok(contents)
     }
 
     /// Unwrap all individual file encryption keys
-    pub fn unwrap_file_keys(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn unwrap_file_keys(&mut self) -> Result<(), BackupError> {
         let keybag = match &self.manifest.keybag {
             Some(kb) => kb,
             None => return Ok(()),
@@ -223,9 +324,13 @@ impl Backup<'_> {
 
         info!("unwrapping file keys...");
         for file in self.files.iter_mut() {
-            if file.fileinfo.is_some() {
-                let mutable = file.fileinfo.as_mut();
-                mutable.map(|s| s.unwrap_encryption_key(keybag));
+            if let Some(fileinfo) = file.fileinfo.as_mut() {
+                if let Err(err) = fileinfo.unwrap_encryption_key(keybag) {
+                    error!(
+                        "failed to unwrap key for {}-{}: {}",
+                        file.domain, file.relative_filename, err
+                    );
+                }
             }
         }
         info!("unwrapping file keys... [done]");
@@ -233,25 +338,42 @@ impl Backup<'_> {
         Ok(())
     }
 
+    /// Read and decrypt `Manifest.db` with the unwrapped manifest key.
+    fn decrypt_manifest_db(&self) -> Result<Vec<u8>, BackupError> {
+        let contents = self.raw_file_read("Manifest.db")?;
+
+        let manifest_key = self
+            .manifest
+            .manifest_key_unwrapped
+            .as_ref()
+            .ok_or(BackupError::NoEncryptionKey)?;
+        let mut decrypted_db = crate::crypto::decrypt_with_key(manifest_key, &contents);
+        if let Some(len) = crate::crypto::pkcs7_unpadded_len(&decrypted_db) {
+            decrypted_db.truncate(len);
+        }
+        debug!("decrypted {} bytes from manifest.", decrypted_db.len());
+
+        Ok(decrypted_db)
+    }
+
     /// Load the list of files, from the backup's manifest file.
-    pub fn parse_manifest(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn parse_manifest(&mut self) -> Result<(), BackupError> {
         self.files.clear();
 
+        // Legacy backups keep an unencrypted mbdb, even when the files are encrypted.
+        if self.format == ManifestFormat::Mbdb {
+            let contents = self.raw_file_read("Manifest.mbdb")?;
+            self.files = parse_mbdb(&contents)?;
+            return Ok(());
+        }
+
         let conn: Connection;
         let tmpf = tempfile::TempDir::new()?;
         let decpath = tmpf.path().join("manifest.db");
 
         {
             if self.manifest.is_encrypted {
-                let contents = self.raw_file_read("Manifest.db")?;
-
-                // let path = format!("{}/Manifest.db", self.path.to_str().unwrap());
-                // let contents = std::fs::read(Path::new(&path)).unwrap();
-                let decrypted_db = crate::lib::crypto::decrypt_with_key(
-                    &self.manifest.manifest_key_unwrapped.as_ref().unwrap(),
-                    &contents,
-                );
-                debug!("decrypted {} bytes from manifest.", decrypted_db.len());
+                let decrypted_db = self.decrypt_manifest_db()?;
 
                 trace!("writing decrypted database: {}", decpath.display());
                 // let decpath = Path::new(&pth);
@@ -265,50 +387,58 @@ impl Backup<'_> {
                 trace!("wrote decrypted database to tmp: {}", decpath.display());
 
                 // std::thread::sleep(std::time::Duration::from_secs(15));
-            } else {
+            } else if let Some(dir) = self.backing.directory() {
                 conn = Connection::open_with_flags(
-                    format!("{}/Manifest.db", self.path.to_str().unwrap()),
+                    dir.join("Manifest.db"),
                     OpenFlags::SQLITE_OPEN_READ_ONLY,
                 )?;
+            } else {
+                // sqlite can only open files, so archived databases are copied out first
+                std::fs::write(&decpath, self.raw_file_read("Manifest.db")?)?;
+                conn = Connection::open_with_flags(&decpath, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
             }
 
             let mut stmt =
                 conn.prepare("SELECT fileid, domain, relativePath, flags, file from Files")?;
-            let rows = stmt
-                .query_map(NO_PARAMS, |row| {
-                    // fileid equals sha1(format!("{}-{}", domain, relative_filename))
-                    let fileid: String = row.get(0)?;
-                    let domain: String = row.get(1)?;
-                    let relative_filename: String = row.get(2)?;
-                    let flags: i64 = row.get(3)?;
-                    let file: Vec<u8> = row.get(4)?;
-                    use plist::Value;
-
-                    let cur = std::io::Cursor::new(file);
-                    let val = Value::from_reader(cur).expect("expected to load bplist");
-
-                    let fileinfo = match FileInfo::try_from(val) {
-                        Ok(res) => Some(res),
-                        Err(err) => {
-                            error!("failed to parse file info: {}", err);
-                            None
-                        }
-                    };
-
-                    Ok(BackupFile {
-                        fileid,
-                        domain,
-                        relative_filename,
-                        flags,
-                        fileinfo,
-                    })
+            let rows = stmt.query_map(NO_PARAMS, |row| {
+                // fileid equals sha1(format!("{}-{}", domain, relative_filename))
+                let fileid: String = row.get(0)?;
+                let domain: String = row.get(1)?;
+                let relative_filename: String = row.get(2)?;
+                let flags: i64 = row.get(3)?;
+                let file: Vec<u8> = row.get(4)?;
+                use plist::Value;
+
+                let cur = std::io::Cursor::new(file);
+                let fileinfo = match Value::from_reader(cur)
+                    .map_err(BackupError::from)
+                    .and_then(FileInfo::try_from)
+                {
+                    Ok(res) => Some(res),
+                    Err(err) => {
+                        let err = BackupError::InvalidFileInfo {
+                            fileid: fileid.clone(),
+                            source: Box::new(err),
+                        };
+                        error!("failed to parse file info: {}", err);
+                        None
+                    }
+                };
+
+                Ok(BackupFile {
+                    fileid,
+                    domain,
+                    relative_filename,
+                    flags,
+                    fileinfo,
                 })
-                .expect("Query to succeed");
+            })?;
 
             // Add each item to the internal list
             for item in rows {
-                if let Ok(item) = item {
-                    self.files.push(item);
+                match item {
+                    Ok(item) => self.files.push(item),
+                    Err(err) => error!("failed to read manifest row: {}", err),
                 }
             }
         }
@@ -318,3 +448,133 @@ impl Backup<'_> {
         Ok(())
     }
 }
+
+/// Name of a file in the backing, from its path relative to the backup root.
+fn entry_name(relative_root: &Option<String>, path: &str) -> String {
+    match relative_root {
+        Some(root) => format!("{}/{}", root, path),
+        None => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_backup_is_sync() {
+        // extract shares a single backup between worker threads
+        assert_send_sync::<Backup>();
+    }
+
+    fn plist_bytes(entries: &[(&str, plist::Value)]) -> Vec<u8> {
+        let mut dict = plist::Dictionary::new();
+        for (key, value) in entries {
+            dict.insert(key.to_string(), value.clone());
+        }
+
+        let mut out = vec![];
+        plist::Value::Dictionary(dict)
+            .to_writer_xml(&mut out)
+            .unwrap();
+        out
+    }
+
+    /// A backup in memory with a `Manifest.db` listing `paths` in `HomeDomain` as regular
+    /// files, without file records, and no file contents.
+    pub(super) fn memory_backing(paths: &[&str]) -> MemoryBacking {
+        use plist::Value;
+        let text = |value: &str| Value::String(value.to_string());
+
+        let mut backing = MemoryBacking::new();
+        backing.insert(
+            "backup/Info.plist",
+            plist_bytes(&[
+                ("Product Type", text("iPhone12,1")),
+                ("Product Version", text("14.2")),
+                ("Target Identifier", text("00008030")),
+                ("Target Type", text("Device")),
+            ]),
+        );
+        backing.insert(
+            "backup/Status.plist",
+            plist_bytes(&[
+                ("BackupState", text("new")),
+                ("Date", text("2020-12-01T00:00:00Z")),
+                ("IsFullBackup", Value::Boolean(false)),
+                ("SnapshotState", text("finished")),
+                ("UUID", text("F5B5F2A6")),
+                ("Version", text("3.3")),
+            ]),
+        );
+        backing.insert(
+            "backup/Manifest.plist",
+            plist_bytes(&[
+                ("IsEncrypted", Value::Boolean(false)),
+                ("Version", text("10.0")),
+                ("Date", text("2020-12-01T00:00:00Z")),
+                ("SystemDomainsVersion", text("24.0")),
+                ("WasPasscodeSet", Value::Boolean(false)),
+                (
+                    "Lockdown",
+                    Value::Dictionary(
+                        plist::Value::from_reader_xml(std::io::Cursor::new(plist_bytes(&[
+                            ("ProductVersion", text("14.2")),
+                            ("ProductType", text("iPhone12,1")),
+                            ("UniqueDeviceID", text("00008030")),
+                            ("SerialNumber", text("F2LXX")),
+                            ("DeviceName", text("iPhone")),
+                        ])))
+                        .unwrap()
+                        .into_dictionary()
+                        .unwrap(),
+                    ),
+                ),
+            ]),
+        );
+
+        // unencrypted databases used to be opened from the filesystem, even in archives
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("Manifest.db");
+        {
+            let conn = Connection::open(&db).unwrap();
+            conn.execute(
+                "CREATE TABLE Files (fileID TEXT, domain TEXT, relativePath TEXT, flags INTEGER, file BLOB)",
+                NO_PARAMS,
+            )
+            .unwrap();
+            for path in paths {
+                conn.execute(
+                    "INSERT INTO Files VALUES (?1, 'HomeDomain', ?2, 1, x'')",
+                    &[&mbdb::compute_fileid("HomeDomain", path), *path],
+                )
+                .unwrap();
+            }
+        }
+        backing.insert("backup/Manifest.db", std::fs::read(&db).unwrap());
+        backing
+    }
+
+    #[test]
+    fn test_memory_backup() {
+        let mut backing = memory_backing(&["Library/a.txt"]);
+        let fileid = mbdb::compute_fileid("HomeDomain", "Library/a.txt");
+        backing.insert(
+            format!("backup/{}/{}", &fileid[0..2], fileid),
+            b"contents".to_vec(),
+        );
+
+        let backup = Backup::with_backing("memory", Box::new(backing))
+            .unwrap()
+            .unlock("")
+            .unwrap();
+        assert_eq!(backup.relative_root.as_deref(), Some("backup"));
+        assert_eq!(backup.format, ManifestFormat::Database);
+
+        let file = backup.find_path("HomeDomain", "Library/a.txt").unwrap();
+        assert_eq!(backup.read_file(&file).unwrap(), b"contents");
+        assert!(backup.verify().unwrap().orphans.is_empty());
+    }
+}
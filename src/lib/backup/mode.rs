@@ -0,0 +1,190 @@
+use std::fmt;
+
+use crate::backup::FileKind;
+
+const S_IFMT: u64 = 0o170000;
+const S_IFREG: u64 = 0o100000;
+const S_IFDIR: u64 = 0o040000;
+const S_IFLNK: u64 = 0o120000;
+
+const S_ISUID: u64 = 0o4000;
+const S_ISGID: u64 = 0o2000;
+const S_ISVTX: u64 = 0o1000;
+
+/// Read, write and execute permission for one class of users.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Access {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Access {
+    fn from_bits(bits: u64) -> Access {
+        Access {
+            read: bits & 0o4 != 0,
+            write: bits & 0o2 != 0,
+            execute: bits & 0o1 != 0,
+        }
+    }
+}
+
+/// A decoded unix file mode, as stored in the `Mode` field of the manifest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileMode {
+    /// The raw mode, including the file type bits
+    pub bits: u64,
+
+    /// File type, from the file type bits
+    pub kind: FileKind,
+
+    pub user: Access,
+    pub group: Access,
+    pub other: Access,
+
+    pub setuid: bool,
+    pub setgid: bool,
+    pub sticky: bool,
+}
+
+impl FileMode {
+    /// The permission bits, including setuid, setgid and sticky, as passed to `chmod`.
+    pub fn permissions(&self) -> u32 {
+        (self.bits & 0o7777) as u32
+    }
+}
+
+impl From<u64> for FileMode {
+    fn from(bits: u64) -> FileMode {
+        FileMode {
+            bits,
+            kind: match bits & S_IFMT {
+                S_IFREG => FileKind::File,
+                S_IFDIR => FileKind::Directory,
+                S_IFLNK => FileKind::Symlink,
+                _ => FileKind::Unknown,
+            },
+            user: Access::from_bits(bits >> 6),
+            group: Access::from_bits(bits >> 3),
+            other: Access::from_bits(bits),
+            setuid: bits & S_ISUID != 0,
+            setgid: bits & S_ISGID != 0,
+            sticky: bits & S_ISVTX != 0,
+        }
+    }
+}
+
+/// Formats the mode like `ls -l`, e.g. `-rw-r--r--` or `drwxr-xr-x`.
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            FileKind::File => '-',
+            FileKind::Directory => 'd',
+            FileKind::Symlink => 'l',
+            FileKind::Unknown => '?',
+        };
+
+        // the execute column shows the special bit instead, lowercase if also executable
+        let triple = |access: &Access, special: bool, set: char| {
+            let execute = match (access.execute, special) {
+                (true, true) => set,
+                (false, true) => set.to_ascii_uppercase(),
+                (true, false) => 'x',
+                (false, false) => '-',
+            };
+            format!(
+                "{}{}{}",
+                if access.read { 'r' } else { '-' },
+                if access.write { 'w' } else { '-' },
+                execute
+            )
+        };
+
+        write!(
+            f,
+            "{}{}{}{}",
+            kind,
+            triple(&self.user, self.setuid, 's'),
+            triple(&self.group, self.setgid, 's'),
+            triple(&self.other, self.sticky, 't')
+        )
+    }
+}
+
+/// BSD file flags (see `chflags(2)`), as stored in the `Flags` field of the manifest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileFlags(pub u64);
+
+/// Flag bits and their names, as shown by `ls -lO`
+const FLAG_NAMES: [(u64, &str); 12] = [
+    (0x0000_0001, "nodump"),
+    (0x0000_0002, "uchg"),
+    (0x0000_0004, "uappnd"),
+    (0x0000_0008, "opaque"),
+    (0x0000_0020, "compressed"),
+    (0x0000_0040, "tracked"),
+    (0x0000_8000, "hidden"),
+    (0x0001_0000, "arch"),
+    (0x0002_0000, "schg"),
+    (0x0004_0000, "sappnd"),
+    (0x0008_0000, "restricted"),
+    (0x0010_0000, "sunlnk"),
+];
+
+impl FileFlags {
+    /// Names of the flags that are set. Unknown bits are left out.
+    pub fn names(&self) -> Vec<&'static str> {
+        FLAG_NAMES
+            .iter()
+            .filter(|(bit, _)| self.0 & bit != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    /// Check if a flag is set, by its `ls -lO` name.
+    pub fn contains(&self, name: &str) -> bool {
+        self.names().contains(&name)
+    }
+}
+
+/// Formats the flags like `ls -lO`: comma separated names, or `-` if there are none.
+impl fmt::Display for FileFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.names();
+        if names.is_empty() {
+            write!(f, "-")
+        } else {
+            write!(f, "{}", names.join(","))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_mode() {
+        let mode = FileMode::from(0o100644);
+        assert_eq!(mode.kind, FileKind::File);
+        assert_eq!(mode.permissions(), 0o644);
+        assert!(mode.user.read && mode.user.write && !mode.user.execute);
+        assert!(mode.other.read && !mode.other.write);
+        assert_eq!(mode.to_string(), "-rw-r--r--");
+
+        assert_eq!(FileMode::from(0o040755).to_string(), "drwxr-xr-x");
+        assert_eq!(FileMode::from(0o120755).kind, FileKind::Symlink);
+        assert_eq!(FileMode::from(0o104755).to_string(), "-rwsr-xr-x");
+        assert_eq!(FileMode::from(0o041777).to_string(), "drwxrwxrwt");
+        assert_eq!(FileMode::from(0o0).kind, FileKind::Unknown);
+    }
+
+    #[test]
+    fn test_file_flags() {
+        let flags = FileFlags(0x8002);
+        assert_eq!(flags.names(), vec!["uchg", "hidden"]);
+        assert!(flags.contains("hidden"));
+        assert_eq!(flags.to_string(), "uchg,hidden");
+        assert_eq!(FileFlags(0).to_string(), "-");
+    }
+}
@@ -0,0 +1,203 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Take};
+use std::path::{Path, PathBuf};
+
+use flate2::read::DeflateDecoder;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::error::BackupError;
+
+/// A readable, seekable handle to a file inside of a backup.
+pub trait ReadSeek: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Reads an uncompressed archive entry directly from the archive file.
+pub(crate) struct StoredEntryReader {
+    file: File,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl StoredEntryReader {
+    /// Read the `len` bytes starting at `start` of `file`.
+    pub(crate) fn new(file: File, start: u64, len: u64) -> StoredEntryReader {
+        StoredEntryReader {
+            file,
+            start,
+            len,
+            pos: 0,
+        }
+    }
+}
+
+impl Read for StoredEntryReader {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.len {
+            return Ok(0);
+        }
+
+        let max = ((self.len - self.pos) as usize).min(out.len());
+        self.file.seek(SeekFrom::Start(self.start + self.pos))?;
+        let count = self.file.read(&mut out[..max])?;
+        self.pos += count as u64;
+
+        Ok(count)
+    }
+}
+
+impl Seek for StoredEntryReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = seek_position(pos, self.pos, self.len)?;
+        Ok(self.pos)
+    }
+}
+
+/// Reads a deflated zip entry, decompressing as it is read.
+///
+/// Seeking forward decompresses and discards, seeking backward restarts the
+/// decompression from the start of the entry.
+struct DeflatedEntryReader {
+    path: PathBuf,
+    start: u64,
+    compressed_len: u64,
+    len: u64,
+    pos: u64,
+    decoder: DeflateDecoder<Take<File>>,
+}
+
+impl DeflatedEntryReader {
+    fn open_decoder(
+        path: &Path,
+        start: u64,
+        compressed_len: u64,
+    ) -> std::io::Result<DeflateDecoder<Take<File>>> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(start))?;
+        Ok(DeflateDecoder::new(file.take(compressed_len)))
+    }
+}
+
+impl Read for DeflatedEntryReader {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.decoder.read(out)?;
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for DeflatedEntryReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = seek_position(pos, self.pos, self.len)?;
+
+        if target < self.pos {
+            self.decoder =
+                DeflatedEntryReader::open_decoder(&self.path, self.start, self.compressed_len)?;
+            self.pos = 0;
+        }
+
+        let skip = target - self.pos;
+        let skipped = std::io::copy(&mut self.by_ref().take(skip), &mut std::io::sink())?;
+        if skipped < skip {
+            // past the end of the entry, further reads return nothing
+            self.pos = target;
+        }
+
+        Ok(self.pos)
+    }
+}
+
+/// Resolve a seek request against the current position and length of a stream.
+fn seek_position(pos: SeekFrom, current: u64, len: u64) -> std::io::Result<u64> {
+    let target = match pos {
+        SeekFrom::Start(offset) => offset as i64,
+        SeekFrom::End(offset) => len as i64 + offset,
+        SeekFrom::Current(offset) => current as i64 + offset,
+    };
+
+    if target < 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "invalid seek to a negative position",
+        ));
+    }
+
+    Ok(target as u64)
+}
+
+/// Open an entry of a zip archive for streaming reads.
+///
+/// Stored and deflated entries are read straight from the archive file at `path`.
+/// Any other compression method is decompressed into a temporary file first.
+pub fn open_archive_entry(
+    path: &Path,
+    archive: &mut ZipArchive<File>,
+    name: &str,
+) -> Result<Box<dyn ReadSeek>, BackupError> {
+    let mut entry = archive.by_name(name)?;
+    let start = entry.data_start();
+    let compressed_len = entry.compressed_size();
+    let len = entry.size();
+
+    match entry.compression() {
+        CompressionMethod::Stored => Ok(Box::new(StoredEntryReader::new(
+            File::open(path)?,
+            start,
+            len,
+        ))),
+        CompressionMethod::Deflated => Ok(Box::new(DeflatedEntryReader {
+            path: path.to_path_buf(),
+            start,
+            compressed_len,
+            len,
+            pos: 0,
+            decoder: DeflatedEntryReader::open_decoder(path, start, compressed_len)?,
+        })),
+        method => {
+            debug!("spooling {:?} zip entry {} to disk", method, name);
+            let mut tmpfile = tempfile::tempfile()?;
+            std::io::copy(&mut entry, &mut tmpfile)?;
+            tmpfile.seek(SeekFrom::Start(0))?;
+            Ok(Box::new(tmpfile))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_zip_entries() {
+        let contents: Vec<u8> = (0..100_000u32).map(|i| (i % 7) as u8).collect();
+
+        let mut zipfile = tempfile::NamedTempFile::new().unwrap();
+        {
+            let mut writer = zip::ZipWriter::new(zipfile.as_file_mut());
+            for (name, method) in &[
+                ("stored", CompressionMethod::Stored),
+                ("deflated", CompressionMethod::Deflated),
+            ] {
+                let options = zip::write::FileOptions::default().compression_method(*method);
+                writer.start_file(*name, options).unwrap();
+                writer.write_all(&contents).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let mut archive = ZipArchive::new(File::open(zipfile.path()).unwrap()).unwrap();
+        for name in &["stored", "deflated"] {
+            let mut reader = open_archive_entry(zipfile.path(), &mut archive, name).unwrap();
+
+            let mut out = vec![];
+            reader.read_to_end(&mut out).unwrap();
+            assert_eq!(out, contents);
+
+            reader.seek(SeekFrom::Start(5000)).unwrap();
+            let mut part = [0u8; 16];
+            reader.read_exact(&mut part).unwrap();
+            assert_eq!(&part[..], &contents[5000..5016]);
+        }
+    }
+}
@@ -1,7 +1,7 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all(deserialize = "PascalCase"))]
 pub struct BackupStatus {
     pub backup_state: String,
     pub date: String,
@@ -0,0 +1,305 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Seek;
+
+use crate::backup::mbdb::compute_fileid;
+use crate::backup::{Backup, BackupFile, FileInfo, FileKind, ManifestFormat};
+use crate::crypto::{unwrap_key, DecryptReader};
+use crate::error::BackupError;
+
+/// A problem `Backup::verify` found with a file in the manifest.
+#[derive(Debug)]
+pub enum VerifyProblem {
+    /// The file id is not the sha1 of the domain and path
+    FileIdMismatch { expected: String },
+    /// The manifest lists the file, but its contents are not in the backup
+    MissingBlob,
+    /// The file record in the manifest could not be decoded
+    NoFileInfo,
+    /// The file key can't be unwrapped with the keybag
+    KeyUnwrapFailed(BackupError),
+    /// The (decrypted) contents are not the size recorded in the manifest
+    SizeMismatch { expected: u64, actual: u64 },
+    /// The decrypted contents don't end in valid PKCS#7 padding
+    InvalidPadding,
+    /// The contents exist, but could not be read
+    Unreadable(BackupError),
+}
+
+impl VerifyProblem {
+    /// A short, stable name for the kind of problem, for machine-readable reports.
+    pub fn name(&self) -> &'static str {
+        match self {
+            VerifyProblem::FileIdMismatch { .. } => "fileid_mismatch",
+            VerifyProblem::MissingBlob => "missing_blob",
+            VerifyProblem::NoFileInfo => "no_file_info",
+            VerifyProblem::KeyUnwrapFailed(_) => "key_unwrap_failed",
+            VerifyProblem::SizeMismatch { .. } => "size_mismatch",
+            VerifyProblem::InvalidPadding => "invalid_padding",
+            VerifyProblem::Unreadable(_) => "unreadable",
+        }
+    }
+}
+
+impl fmt::Display for VerifyProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyProblem::FileIdMismatch { expected } => {
+                write!(f, "file id does not match its path, expected {}", expected)
+            }
+            VerifyProblem::MissingBlob => write!(f, "contents are missing from the backup"),
+            VerifyProblem::NoFileInfo => write!(f, "file record could not be decoded"),
+            VerifyProblem::KeyUnwrapFailed(err) => write!(f, "file key does not unwrap: {}", err),
+            VerifyProblem::SizeMismatch { expected, actual } => write!(
+                f,
+                "size {} does not match the manifest size {}",
+                actual, expected
+            ),
+            VerifyProblem::InvalidPadding => write!(f, "decrypted contents have invalid padding"),
+            VerifyProblem::Unreadable(err) => write!(f, "contents can't be read: {}", err),
+        }
+    }
+}
+
+/// A problem with a single file in the manifest.
+#[derive(Debug)]
+pub struct VerifyIssue {
+    pub fileid: String,
+    pub domain: String,
+    pub relative_filename: String,
+    pub problem: VerifyProblem,
+}
+
+/// The result of checking a backup with `Backup::verify`.
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Number of records in the manifest
+    pub files: usize,
+    /// Number of file contents that were checked
+    pub blobs: usize,
+    /// Problems with files in the manifest, in manifest order
+    pub issues: Vec<VerifyIssue>,
+    /// Paths of stored file contents that no manifest record refers to,
+    /// relative to the backup root
+    pub orphans: Vec<String>,
+}
+
+impl VerifyReport {
+    /// True if no problems and no orphaned contents were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty() && self.orphans.is_empty()
+    }
+}
+
+impl Backup {
+    /// Check the backup for consistency.
+    ///
+    /// Every file id must be the sha1 of its domain and path, and every file must have
+    /// contents of the size recorded in the manifest. For encrypted backups, every file
+    /// key must unwrap, and the size is checked against the decrypted contents.
+    /// Empty files are not checked, since iOS often stores them without a key or contents.
+    /// Stored contents that aren't in the manifest are listed as orphans.
+    ///
+    /// The manifest must be loaded first, see `unlock`. Only the last block of an
+    /// encrypted file is decrypted, so this doesn't read the whole backup.
+    pub fn verify(&self) -> Result<VerifyReport, BackupError> {
+        let mut report = VerifyReport {
+            files: self.files.len(),
+            ..VerifyReport::default()
+        };
+
+        for file in &self.files {
+            let mut problems = vec![];
+
+            let expected = compute_fileid(&file.domain, &file.relative_filename);
+            if file.fileid != expected {
+                problems.push(VerifyProblem::FileIdMismatch { expected });
+            }
+
+            let empty = file.fileinfo.as_ref().is_some_and(|info| info.size == 0);
+            if file.kind() == FileKind::File && !empty {
+                report.blobs += 1;
+                if let Err(problem) = self.verify_contents(file) {
+                    problems.push(problem);
+                }
+            }
+
+            report
+                .issues
+                .extend(problems.into_iter().map(|problem| VerifyIssue {
+                    fileid: file.fileid.clone(),
+                    domain: file.domain.clone(),
+                    relative_filename: file.relative_filename.clone(),
+                    problem,
+                }));
+        }
+
+        let referenced: HashSet<String> =
+            self.files.iter().map(|file| self.blob_path(file)).collect();
+        report.orphans = self
+            .stored_paths()?
+            .into_iter()
+            .filter(|path| is_blob_path(path, self.format) && !referenced.contains(path))
+            .collect();
+        report.orphans.sort();
+
+        Ok(report)
+    }
+
+    /// Check that a file's contents exist and have the size recorded in the manifest.
+    fn verify_contents(&self, file: &BackupFile) -> Result<(), VerifyProblem> {
+        let info = file.fileinfo.as_ref().ok_or(VerifyProblem::NoFileInfo)?;
+
+        let key = if self.manifest.is_encrypted {
+            Some(
+                self.file_key(info)
+                    .map_err(VerifyProblem::KeyUnwrapFailed)?,
+            )
+        } else {
+            None
+        };
+
+        let mut contents = match self.raw_file_open(&self.blob_path(file)) {
+            Ok(contents) => contents,
+            Err(BackupError::InManifestButNotFound) => return Err(VerifyProblem::MissingBlob),
+            Err(err) => return Err(VerifyProblem::Unreadable(err)),
+        };
+
+        let unreadable = |err: std::io::Error| VerifyProblem::Unreadable(BackupError::Io(err));
+        let actual = match key {
+            Some(key) => {
+                let mut reader = DecryptReader::new(contents, &key).map_err(unreadable)?;
+                match reader.pkcs7_len().map_err(unreadable)? {
+                    Some(len) => len,
+                    // a truncated file usually ends mid-plaintext, without padding
+                    None if reader.padded_len() < info.size => reader.padded_len(),
+                    None => return Err(VerifyProblem::InvalidPadding),
+                }
+            }
+            None => contents
+                .seek(std::io::SeekFrom::End(0))
+                .map_err(unreadable)?,
+        };
+
+        if actual != info.size {
+            return Err(VerifyProblem::SizeMismatch {
+                expected: info.size,
+                actual,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The unwrapped key of a file, unwrapping it again to find out why if it is missing.
+    fn file_key(&self, info: &FileInfo) -> Result<Vec<u8>, BackupError> {
+        if let Some(key) = &info.encryption_key {
+            return Ok(key.clone());
+        }
+
+        let wrapped = info
+            .wrapped_encryption_key
+            .as_ref()
+            .ok_or(BackupError::NoEncryptionKey)?;
+        let keybag = self.get_keybag().ok_or(BackupError::NoKeybag)?;
+        let class_key = keybag
+            .find_class_key(&info.protection_class)
+            .ok_or(BackupError::NoClassKey(info.protection_class))?;
+
+        unwrap_key(&class_key, wrapped)
+    }
+
+    /// Paths of every file stored in the backup, relative to the backup root.
+    fn stored_paths(&self) -> Result<Vec<String>, BackupError> {
+        let prefix = match &self.relative_root {
+            Some(root) => format!("{}/", root),
+            None => String::new(),
+        };
+
+        Ok(self
+            .backing
+            .entries()?
+            .into_iter()
+            .filter_map(|name| {
+                name.strip_prefix(prefix.as_str())
+                    .map(|name| name.to_string())
+            })
+            .collect())
+    }
+}
+
+/// Check if a path relative to the backup root is where file contents are stored:
+/// `xx/<fileid>` for `Manifest.db` backups, `<fileid>` for `Manifest.mbdb` backups.
+fn is_blob_path(path: &str, format: ManifestFormat) -> bool {
+    let is_fileid = |name: &str| name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit());
+
+    match (format, path.split_once('/')) {
+        (ManifestFormat::Database, Some((dir, name))) => is_fileid(name) && name.starts_with(dir),
+        (ManifestFormat::Mbdb, None) => is_fileid(path),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_blob_path() {
+        let fileid = compute_fileid("HomeDomain", "Library/SMS/sms.db");
+        let nested = format!("{}/{}", &fileid[0..2], fileid);
+
+        assert!(is_blob_path(&nested, ManifestFormat::Database));
+        assert!(!is_blob_path(&fileid, ManifestFormat::Database));
+        assert!(!is_blob_path(
+            &format!("00/{}", fileid),
+            ManifestFormat::Database
+        ));
+        assert!(!is_blob_path("Manifest.db", ManifestFormat::Database));
+
+        assert!(is_blob_path(&fileid, ManifestFormat::Mbdb));
+        assert!(!is_blob_path(&nested, ManifestFormat::Mbdb));
+        assert!(!is_blob_path("Manifest.mbdb", ManifestFormat::Mbdb));
+    }
+
+    #[test]
+    fn test_empty_file_without_key() {
+        let backing = crate::backup::tests::memory_backing(&["Library/empty", "Library/full"]);
+        let mut backup = Backup::with_backing("memory", Box::new(backing))
+            .unwrap()
+            .unlock("")
+            .unwrap();
+
+        // an encrypted backup, where neither file has a key or contents
+        backup.manifest.is_encrypted = true;
+        for (file, size) in backup.files.iter_mut().zip(&[0, 10]) {
+            file.fileinfo = Some(FileInfo {
+                last_modified: 0,
+                last_status_change: 0,
+                last_accessed: None,
+                birth: 0,
+                flags: 0,
+                inode: 0,
+                group_id: 0,
+                user_id: 0,
+                size: *size,
+                mode: 0o100644,
+                protection_class: crate::crypto::ProtectionClass::NSFileProtectionNone,
+                wrapped_encryption_key: None,
+                wrapped_encryption_class: None,
+                encryption_key: None,
+                extended_attributes: Default::default(),
+                target: None,
+            });
+        }
+
+        let report = backup.verify().unwrap();
+        assert_eq!(report.blobs, 1);
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].relative_filename, "Library/full");
+        assert!(matches!(
+            report.issues[0].problem,
+            VerifyProblem::KeyUnwrapFailed(BackupError::NoEncryptionKey)
+        ));
+    }
+}
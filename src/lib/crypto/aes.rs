@@ -2,7 +2,8 @@ use log::{trace, warn};
 
 use ::crypto::buffer::{RefReadBuffer, RefWriteBuffer};
 
-use crate::lib::util::{pack_u64, unpack_64_bit};
+use crate::error::BackupError;
+use crate::util::{pack_u64, unpack_64_bit};
 
 /// perform aes_cbc_256
 pub fn decrypt_with_key(key: &Vec<u8>, data: &Vec<u8>) -> Vec<u8> {
@@ -25,21 +26,41 @@ pub fn decrypt_with_key(key: &Vec<u8>, data: &Vec<u8>) -> Vec<u8> {
     return out;
 }
 
-pub fn unwrap_key(kek: &[u8], wpky: &Vec<u8>) -> Vec<u8> {
+/// Find the length of PKCS#7 padded data once the padding is removed.
+/// Returns None if the padding is invalid.
+pub fn pkcs7_unpadded_len(data: &[u8]) -> Option<usize> {
+    let pad = *data.last()? as usize;
+    if pad == 0 || pad > 16 || pad > data.len() {
+        return None;
+    }
+
+    if data[data.len() - pad..].iter().all(|&b| b as usize == pad) {
+        Some(data.len() - pad)
+    } else {
+        None
+    }
+}
+
+/// RFC 3394 AES key unwrap.
+/// Fails with `BackupError::WrongPassword` if the integrity check fails, which
+/// means the key encryption key is wrong.
+pub fn unwrap_key(kek: &[u8], wpky: &Vec<u8>) -> Result<Vec<u8>, BackupError> {
     trace!("Key: {:x?}", kek);
     trace!("Wrapped: {:x?}", wpky);
 
     trace!("unwrapping key!");
+    // wrapped keys are the iv plus at least two 64 bit blocks, and only aes-256 is used
+    if kek.len() != 32 || wpky.len() < 24 || !wpky.len().is_multiple_of(8) {
+        return Err(BackupError::InvalidWrappedKey);
+    }
+
     let mut c: Vec<u64> = vec![];
 
     for i in 0..(wpky.len() / 8) {
         let slice: &[u8] = &wpky.as_slice()[i * 8..i * 8 + 8];
-        let val = unpack_64_bit(&slice);
-
-        if let Some(val) = val {
-            c.push(u64::from_be_bytes(val));
-        } else {
-            panic!("Couldn't unwrap 64 bit value from provided wrapper.");
+        match unpack_64_bit(&slice) {
+            Some(val) => c.push(u64::from_be_bytes(val)),
+            None => return Err(BackupError::InvalidWrappedKey),
         }
     }
 
@@ -115,7 +136,7 @@ pub fn unwrap_key(kek: &[u8], wpky: &Vec<u8>) -> Vec<u8> {
 
     if a != 0xa6a6a6a6a6a6a6a6 {
         warn!("got iv: 0x{:x}, expected: 0xa6a6a6a6a6a6a6a6", a);
-        panic!("unexpected resulant iv. this is usually caused by an invalid password to the backup. If this occcurs midaway through an operation, please file an issue on the project issue tracker.");
+        return Err(BackupError::WrongPassword);
     }
 
     let mut result: Vec<u8> = Vec::new();
@@ -131,5 +152,88 @@ pub fn unwrap_key(kek: &[u8], wpky: &Vec<u8>) -> Vec<u8> {
 
     trace!("decrypt result: {}", hex::encode(&result));
 
-    return result;
+    Ok(result)
+}
+
+/// RFC 3394 AES key wrap, the inverse of `unwrap_key`.
+pub fn wrap_key(kek: &[u8], key: &[u8]) -> Result<Vec<u8>, BackupError> {
+    if kek.len() != 32 || key.len() < 16 || !key.len().is_multiple_of(8) {
+        return Err(BackupError::InvalidWrappedKey);
+    }
+
+    let mut r: Vec<u64> = key
+        .chunks(8)
+        .map(|chunk| u64::from_be_bytes(unpack_64_bit(chunk).unwrap()))
+        .collect();
+    let n = r.len();
+    let mut a: u64 = 0xa6a6a6a6a6a6a6a6;
+
+    for j in 0..6 {
+        for (i, value) in r.iter_mut().enumerate() {
+            let mut packed = a.to_be_bytes().to_vec();
+            packed.extend_from_slice(&value.to_be_bytes());
+
+            let mut enc = ::crypto::aes::ecb_encryptor(
+                ::crypto::aes::KeySize::KeySize256,
+                kek,
+                ::crypto::blockmodes::NoPadding,
+            );
+
+            let mut out: Vec<u8> = vec![0u8; 16];
+            let mut output = RefWriteBuffer::new(out.as_mut_slice());
+            let mut input = RefReadBuffer::new(packed.as_slice());
+            if enc.encrypt(&mut input, &mut output, true).is_err() {
+                return Err(BackupError::InvalidWrappedKey);
+            }
+
+            let t = (n * j + i + 1) as u64;
+            a = u64::from_be_bytes(unpack_64_bit(&out[0..8]).unwrap()) ^ t;
+            *value = u64::from_be_bytes(unpack_64_bit(&out[8..16]).unwrap());
+        }
+    }
+
+    let mut result = pack_u64(a).to_vec();
+    for value in r {
+        result.extend_from_slice(&pack_u64(value));
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 3394 section 4.6, 256 bits of key data with a 256-bit KEK
+    const KEK: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEY: &str = "00112233445566778899aabbccddeeff000102030405060708090a0b0c0d0e0f";
+    const WRAPPED: &str =
+        "28c9f404c4b810f4cbccb35cfb87f8263f5786e2d80ed326cbc7f0e71a99f43bfb988b9b7a02dd21";
+
+    #[test]
+    fn test_pkcs7() {
+        assert_eq!(pkcs7_unpadded_len(&[1, 2, 3, 3, 3, 3]), Some(3));
+        assert_eq!(pkcs7_unpadded_len(&[16u8; 16]), Some(0));
+        assert_eq!(pkcs7_unpadded_len(&[1, 2, 3, 2]), None);
+        assert_eq!(pkcs7_unpadded_len(&[1, 2, 0]), None);
+        assert_eq!(pkcs7_unpadded_len(&[]), None);
+    }
+
+    #[test]
+    fn test_unwrap_key() {
+        let key = unwrap_key(&hex::decode(KEK).unwrap(), &hex::decode(WRAPPED).unwrap());
+        assert_eq!(hex::encode(key.unwrap()), KEY);
+    }
+
+    #[test]
+    fn test_wrap_key() {
+        let wrapped = wrap_key(&hex::decode(KEK).unwrap(), &hex::decode(KEY).unwrap());
+        assert_eq!(hex::encode(wrapped.unwrap()), WRAPPED);
+    }
+
+    #[test]
+    fn test_unwrap_key_wrong_kek() {
+        let key = unwrap_key(&[0u8; 32], &hex::decode(WRAPPED).unwrap());
+        assert!(matches!(key, Err(BackupError::WrongPassword)));
+    }
 }
@@ -0,0 +1,257 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use ::crypto::buffer::{RefReadBuffer, RefWriteBuffer};
+
+/// AES block size in bytes
+const BLOCK_SIZE: u64 = 16;
+
+/// Amount of ciphertext decrypted at a time, must be a multiple of the block size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Decrypts an aes_cbc_256 stream (zero iv) as it is read.
+///
+/// Because every CBC block only depends on the previous ciphertext block,
+/// seeking only needs to read one extra block to recover the iv.
+pub struct DecryptReader<R: Read + Seek> {
+    inner: R,
+    key: Vec<u8>,
+
+    /// Total length of the ciphertext
+    padded_len: u64,
+
+    /// Length of the plaintext, reads stop here
+    len: u64,
+
+    /// Current position in the plaintext
+    pos: u64,
+
+    /// Decrypted chunk, starting at `buf_start`
+    buf: Vec<u8>,
+    buf_start: u64,
+
+    /// Offset and value of the last ciphertext block read, which is the iv of the next chunk
+    chain: Option<(u64, [u8; 16])>,
+}
+
+impl<R: Read + Seek> DecryptReader<R> {
+    pub fn new(mut inner: R, key: &[u8]) -> std::io::Result<DecryptReader<R>> {
+        let len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+
+        Ok(DecryptReader {
+            inner,
+            key: key.to_vec(),
+            padded_len: len,
+            len,
+            pos: 0,
+            buf: vec![],
+            buf_start: 0,
+            chain: None,
+        })
+    }
+
+    /// Length of the ciphertext, which is also the length of the still padded plaintext
+    pub fn padded_len(&self) -> u64 {
+        self.padded_len
+    }
+
+    /// Limit the plaintext to `len` bytes, which strips the padding off of the end.
+    pub fn set_len(&mut self, len: u64) {
+        self.len = len.min(self.padded_len);
+    }
+
+    /// Decrypt the last block, and find the plaintext length once PKCS#7 padding is removed.
+    /// Returns None if the padding is invalid.
+    pub fn pkcs7_len(&mut self) -> std::io::Result<Option<u64>> {
+        if self.padded_len < BLOCK_SIZE {
+            return Ok(None);
+        }
+
+        let pos = self.pos;
+        self.pos = self.padded_len - BLOCK_SIZE;
+        self.fill()?;
+        self.pos = pos;
+
+        let offset = (self.padded_len - BLOCK_SIZE - self.buf_start) as usize;
+        let last = &self.buf[offset..offset + BLOCK_SIZE as usize];
+        Ok(crate::crypto::pkcs7_unpadded_len(last)
+            .map(|len| self.padded_len - BLOCK_SIZE + len as u64))
+    }
+
+    /// Decrypt the chunk containing the current position.
+    fn fill(&mut self) -> std::io::Result<()> {
+        let start = self.pos - self.pos % BLOCK_SIZE;
+
+        let mut iv = [0u8; 16];
+        match self.chain {
+            _ if start == 0 => {}
+            Some((offset, chained)) if offset == start => iv = chained,
+            _ => {
+                self.inner.seek(SeekFrom::Start(start - BLOCK_SIZE))?;
+                self.inner.read_exact(&mut iv)?;
+            }
+        }
+
+        if self.chain.map(|(offset, _)| offset) != Some(start) {
+            self.inner.seek(SeekFrom::Start(start))?;
+        }
+
+        let mut ciphertext = vec![0u8; CHUNK_SIZE];
+        let mut read = 0;
+        while read < CHUNK_SIZE {
+            match self.inner.read(&mut ciphertext[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+
+        if !(read as u64).is_multiple_of(BLOCK_SIZE) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "ciphertext is not a multiple of the block size",
+            ));
+        }
+        ciphertext.truncate(read);
+
+        let mut plaintext = vec![0u8; read];
+        if read > 0 {
+            let mut dec = ::crypto::aes::cbc_decryptor(
+                ::crypto::aes::KeySize::KeySize256,
+                self.key.as_slice(),
+                &iv,
+                ::crypto::blockmodes::NoPadding,
+            );
+
+            let mut output = RefWriteBuffer::new(plaintext.as_mut_slice());
+            let mut input = RefReadBuffer::new(ciphertext.as_slice());
+            if dec.decrypt(&mut input, &mut output, true).is_err() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "failed to decrypt chunk",
+                ));
+            }
+
+            let mut last = [0u8; 16];
+            last.copy_from_slice(&ciphertext[read - BLOCK_SIZE as usize..]);
+            self.chain = Some((start + read as u64, last));
+        }
+
+        self.buf = plaintext;
+        self.buf_start = start;
+
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for DecryptReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.len || out.is_empty() {
+            return Ok(0);
+        }
+
+        let buf_end = self.buf_start + self.buf.len() as u64;
+        if self.pos < self.buf_start || self.pos >= buf_end {
+            self.fill()?;
+        }
+
+        let offset = (self.pos - self.buf_start) as usize;
+        let available = &self.buf[offset..];
+        if available.is_empty() {
+            return Ok(0);
+        }
+
+        let count = available
+            .len()
+            .min(out.len())
+            .min((self.len - self.pos) as usize);
+        out[..count].copy_from_slice(&available[..count]);
+        self.pos += count as u64;
+
+        Ok(count)
+    }
+}
+
+impl<R: Read + Seek> Seek for DecryptReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => self.len as i64 + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+
+        if target < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            ));
+        }
+
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn encrypt(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut enc = ::crypto::aes::cbc_encryptor(
+            ::crypto::aes::KeySize::KeySize256,
+            key,
+            &[0u8; 16],
+            ::crypto::blockmodes::NoPadding,
+        );
+
+        let mut out = vec![0u8; data.len()];
+        let mut output = RefWriteBuffer::new(out.as_mut_slice());
+        let mut input = RefReadBuffer::new(data);
+        enc.encrypt(&mut input, &mut output, true).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_stream_and_seek() {
+        let key = [7u8; 32];
+        let plaintext: Vec<u8> = (0..(CHUNK_SIZE * 2 + 64))
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let ciphertext = encrypt(&key, &plaintext);
+
+        let mut reader = DecryptReader::new(Cursor::new(ciphertext), &key).unwrap();
+        let mut out = vec![];
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, plaintext);
+
+        // seek into the middle of a block in the second chunk
+        let offset = CHUNK_SIZE as u64 + 21;
+        reader.seek(SeekFrom::Start(offset)).unwrap();
+        let mut part = [0u8; 40];
+        reader.read_exact(&mut part).unwrap();
+        assert_eq!(&part[..], &plaintext[offset as usize..offset as usize + 40]);
+
+        reader.seek(SeekFrom::End(-5)).unwrap();
+        let mut tail = vec![];
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(&tail[..], &plaintext[plaintext.len() - 5..]);
+    }
+
+    #[test]
+    fn test_padding() {
+        let key = [9u8; 32];
+        let mut plaintext: Vec<u8> = (0..100u8).collect();
+        plaintext.extend_from_slice(&[12u8; 12]);
+        let ciphertext = encrypt(&key, &plaintext);
+
+        let mut reader = DecryptReader::new(Cursor::new(ciphertext), &key).unwrap();
+        assert_eq!(reader.padded_len(), 112);
+        assert_eq!(reader.pkcs7_len().unwrap(), Some(100));
+
+        reader.set_len(100);
+        let mut out = vec![];
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, &plaintext[..100]);
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 100);
+    }
+}
@@ -3,7 +3,8 @@ use log::debug;
 use hex;
 use uuid::Uuid;
 
-use crate::lib::crypto::*;
+use crate::crypto::*;
+use crate::error::BackupError;
 use ring::pbkdf2;
 
 #[derive(Debug)]
@@ -20,6 +21,8 @@ pub struct KeyBag {
     pub wrap: u32,
     pub keys: Vec<KeybagEntry>,
     pub key: Option<Vec<u8>>,
+    /// Order of the root blocks as they were parsed
+    pub layout: Vec<KeybagLayout>,
 }
 
 impl KeyBag {
@@ -42,13 +45,38 @@ pub struct KeybagEntry {
     pub wrap: u32,
     pub key_type: KeyTypes,
     pub wpky: Vec<u8>,
+    /// Public key of an asymmetric (Curve25519) class key
+    pub pbky: Option<Vec<u8>>,
     pub key: Option<Vec<u8>>,
+    /// Order of the class key blocks as they were parsed
+    pub layout: Vec<KeybagLayout>,
+}
+
+/// A block as it appeared in a parsed keybag, so `to_bytes` can keep the original layout.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeybagLayout {
+    /// A tag `init` decodes, written back from the current value of its field
+    Known(KeybagBlockTag),
+    /// A tag `init` doesn't know, written back unchanged
+    Unknown(String, Vec<u8>),
+}
+
+impl KeybagLayout {
+    fn from_block(block: &BackupKeyBagBlock) -> KeybagLayout {
+        match block.tag {
+            KeybagBlockTag::Unknown => {
+                KeybagLayout::Unknown(block.name.clone(), block.data.clone())
+            }
+            ref tag => KeybagLayout::Known(tag.clone()),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 #[allow(unused)]
 pub struct BackupKeyBagBlock {
     tag: KeybagBlockTag,
+    name: String,
     length: usize,
     data: Vec<u8>,
 }
@@ -73,6 +101,11 @@ impl KeyBag {
             }
         }
 
+        // the last class key isn't followed by another uuid
+        if !section.is_empty() {
+            sections.push(section);
+        }
+
         sections
     }
 
@@ -94,16 +127,18 @@ impl KeyBag {
         return root_entries;
     }
 
-    pub fn unlock_with_key(&mut self, passcode_key: Vec<u8>) {
-        self.key = Some(passcode_key.clone());
+    /// Unwrap the class keys with a derived passcode key.
+    /// The keybag is left untouched if any of the class keys fail to unwrap.
+    pub fn unlock_with_key(&mut self, passcode_key: Vec<u8>) -> Result<(), BackupError> {
+        let mut unwrapped = vec![];
+        for key in &self.keys {
+            unwrapped.push(crate::crypto::aes::unwrap_key(&passcode_key, &key.wpky)?);
+        }
 
-        for key in self.keys.iter_mut() {
-            // let k = key.wpky;
-            key.key = Some(crate::lib::crypto::aes::unwrap_key(
-                &passcode_key,
-                &key.wpky,
-            ));
+        for (key, class_key) in self.keys.iter_mut().zip(unwrapped) {
+            key.key = Some(class_key);
         }
+        self.key = Some(passcode_key);
 
         info!("unwrapped {} keys.", self.keys.len());
         for key in &self.keys {
@@ -147,61 +182,234 @@ impl KeyBag {
         //             return False
         //         classkey["KEY"] = k
         // return True
+
+        Ok(())
     }
 
-    pub fn unlock_with_passcode(&mut self, passcode: &str) {
+    pub fn unlock_with_passcode(&mut self, passcode: &str) -> Result<(), BackupError> {
         info!("deriving keys...");
         #[cfg(debug_assertions)]
         warn!("key derivation is slow in non-release mode.");
-        let mut passcode1: Vec<u8> = vec![0u8; 32];
-        let mut passcode_key: Vec<u8> = vec![0u8; 32];
 
-        let dpic = self.dpic.unwrap();
-        let iterations = self.iterations.unwrap();
-        let double_protection_salt = self.double_protection_salt.as_ref().unwrap();
+        let passcode_key = self.derive_passcode_key(passcode);
+
+        info!("deriving keys [done]");
+
+        self.unlock_with_key(passcode_key)
+    }
+
+    /// Derive the passcode key used to unwrap the class keys.
+    ///
+    /// iOS 10.2 and later keybags contain DPSL/DPIC, and run an additional
+    /// round of pbkdf2-sha256 before the pbkdf2-sha1 round. Older keybags only
+    /// have SALT/ITER and use the single pbkdf2-sha1 round.
+    pub fn derive_passcode_key(&self, passcode: &str) -> Vec<u8> {
+        let iterations = self.iterations.unwrap_or(1);
 
-        debug!("dpic: {}", dpic);
-        debug!("dpsl: {:?}", double_protection_salt);
         debug!("iterations: {}", iterations);
         debug!("salt: {:?}", self.salt);
         debug!("deriving keys... (this may take a while)");
-        debug!("1. pbkdf2-sha256(it: {}, ps: {})", dpic, passcode);
 
-        // 1. Round of pbkdf2-sha256(passcode)
-        pbkdf2::derive(
-            ring::pbkdf2::PBKDF2_HMAC_SHA256,
-            std::num::NonZeroU32::new(dpic as u32).unwrap(),
-            &double_protection_salt.as_slice(),
-            passcode.as_bytes(),
-            passcode1.as_mut_slice(),
-        );
+        let passcode1 = match (self.dpic, self.double_protection_salt.as_ref()) {
+            (Some(dpic), Some(double_protection_salt)) => {
+                let mut passcode1: Vec<u8> = vec![0u8; 32];
+
+                debug!("dpic: {}", dpic);
+                debug!("dpsl: {:?}", double_protection_salt);
+                debug!("1. pbkdf2-sha256(it: {}, ps: {})", dpic, passcode);
+
+                // 1. Round of pbkdf2-sha256(passcode)
+                pbkdf2::derive(
+                    ring::pbkdf2::PBKDF2_HMAC_SHA256,
+                    std::num::NonZeroU32::new(dpic.max(1)).unwrap(),
+                    &double_protection_salt.as_slice(),
+                    passcode.as_bytes(),
+                    passcode1.as_mut_slice(),
+                );
+                debug!("done.");
+
+                passcode1
+            }
+            _ => {
+                debug!("no double protection salt, using legacy key derivation");
+                passcode.as_bytes().to_vec()
+            }
+        };
 
-        // 2. Round of pbkdf2-sha1(pbkdf2-sha256(passcode))
-        debug!("done.");
+        // 2. Round of pbkdf2-sha1(pbkdf2-sha256(passcode)), or pbkdf2-sha1(passcode) on legacy keybags
+        let mut passcode_key: Vec<u8> = vec![0u8; 32];
         debug!(
-            "2. pbkdf2-sha2(it: {}, ps: {})",
+            "2. pbkdf2-sha1(it: {}, ps: {})",
             iterations,
             hex::encode(&passcode1)
         );
         pbkdf2::derive(
             ring::pbkdf2::PBKDF2_HMAC_SHA1,
-            std::num::NonZeroU32::new(iterations as u32).unwrap(),
+            std::num::NonZeroU32::new(iterations.max(1)).unwrap(),
             &self.salt.as_slice(),
             passcode1.as_slice(),
             passcode_key.as_mut_slice(),
         );
 
         debug!("3. result = {}", hex::encode(&passcode_key));
-        debug!("{:?}", passcode_key);
 
-        info!("deriving keys [done]");
-        // crypto::pbkdf2::pbkdf2(&mut mac, &self.double_protection_salt.as_slice(), self.dpic, passcode1.as_mut_slice());
-        // crypto::pbkdf2::pbkdf2(&mut sha1, &self.salt.as_slice(), self.iterations, passcode_key.as_mut_slice());
+        passcode_key
+    }
+
+    /// Re-protect the unlocked keybag with a new passcode.
+    ///
+    /// New salts are generated and every class key is wrapped with the new passcode key.
+    /// The class keys themselves don't change, so anything wrapped with them stays valid.
+    /// The pbkdf2-sha1 (`ITER`) and pbkdf2-sha256 (`DPIC`) iteration counts are replaced
+    /// if given, and kept otherwise. `DPIC` can only be set on keybags that have a `DPSL`.
+    pub fn set_passcode(
+        &mut self,
+        passcode: &str,
+        iterations: Option<u32>,
+        dpic: Option<u32>,
+    ) -> Result<(), BackupError> {
+        let random = ring::rand::SystemRandom::new();
+        let fill = |len: usize| -> Result<Vec<u8>, BackupError> {
+            let mut out = vec![0u8; len];
+            ring::rand::SecureRandom::fill(&random, &mut out)
+                .map_err(|_| std::io::Error::other("failed to generate salt"))?;
+            Ok(out)
+        };
+
+        // every class key must be unwrapped, or it would be lost
+        for key in &self.keys {
+            if key.key.is_none() {
+                return Err(BackupError::NoClassKey(key.class));
+            }
+        }
+
+        if dpic.is_some() && self.double_protection_salt.is_none() {
+            return Err(BackupError::KeybagMissingTag(KeybagBlockTag::DPSL));
+        }
+        if iterations.is_some() {
+            self.iterations = iterations;
+        }
+        if dpic.is_some() {
+            self.dpic = dpic;
+        }
+
+        self.salt = fill(self.salt.len().max(20))?;
+        if let Some(ref salt) = self.double_protection_salt {
+            let len = salt.len().max(20);
+            self.double_protection_salt = Some(fill(len)?);
+        }
+
+        let passcode_key = self.derive_passcode_key(passcode);
+        for key in self.keys.iter_mut() {
+            if let Some(ref class_key) = key.key {
+                key.wpky = crate::crypto::aes::wrap_key(&passcode_key, class_key)?;
+            }
+        }
+        self.key = Some(passcode_key);
+
+        Ok(())
+    }
+
+    /// Serialize the keybag back to the TLV blocks `init` reads.
+    /// Blocks are written in the order they were parsed, including the ones `init`
+    /// doesn't understand, so an unchanged keybag serializes to the same bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BackupError> {
+        let kind = self
+            .kind
+            .value()
+            .ok_or(BackupError::KeybagInvalidValue(KeybagBlockTag::TYPE))?;
+
+        let mut root = vec![
+            (KeybagBlockTag::VERS, self.version.to_be_bytes().to_vec()),
+            (KeybagBlockTag::TYPE, kind.to_be_bytes().to_vec()),
+            (KeybagBlockTag::UUID, self.uuid.as_bytes().to_vec()),
+            (KeybagBlockTag::HMCK, self.hmck.clone()),
+            (KeybagBlockTag::WRAP, self.wrap.to_be_bytes().to_vec()),
+            (KeybagBlockTag::SALT, self.salt.clone()),
+        ];
+        if let Some(iterations) = self.iterations {
+            root.push((KeybagBlockTag::ITER, iterations.to_be_bytes().to_vec()));
+        }
+        if let Some(dpwt) = self.dpwt {
+            root.push((KeybagBlockTag::DPWT, dpwt.to_be_bytes().to_vec()));
+        }
+        if let Some(dpic) = self.dpic {
+            root.push((KeybagBlockTag::DPIC, dpic.to_be_bytes().to_vec()));
+        }
+        if let Some(ref dpsl) = self.double_protection_salt {
+            root.push((KeybagBlockTag::DPSL, dpsl.clone()));
+        }
+
+        let mut out = vec![];
+        KeyBag::write_blocks(&mut out, root, &self.layout);
+
+        for key in &self.keys {
+            let class: u32 = key.class.into();
+            let key_type = key
+                .key_type
+                .value()
+                .ok_or(BackupError::KeybagInvalidValue(KeybagBlockTag::KTYP))?;
+
+            let mut blocks = vec![
+                (KeybagBlockTag::UUID, key.uuid.as_bytes().to_vec()),
+                (KeybagBlockTag::CLAS, class.to_be_bytes().to_vec()),
+                (KeybagBlockTag::WRAP, key.wrap.to_be_bytes().to_vec()),
+                (KeybagBlockTag::KTYP, key_type.to_be_bytes().to_vec()),
+                (KeybagBlockTag::WPKY, key.wpky.clone()),
+            ];
+            if let Some(ref pbky) = key.pbky {
+                blocks.push((KeybagBlockTag::PBKY, pbky.clone()));
+            }
+            KeyBag::write_blocks(&mut out, blocks, &key.layout);
+        }
+
+        Ok(out)
+    }
+
+    /// Write `blocks` in the order of `layout`, followed by any blocks it doesn't mention.
+    fn write_blocks(
+        out: &mut Vec<u8>,
+        mut blocks: Vec<(KeybagBlockTag, Vec<u8>)>,
+        layout: &[KeybagLayout],
+    ) {
+        fn push(out: &mut Vec<u8>, tag: &str, data: &[u8]) {
+            out.extend_from_slice(tag.as_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out.extend_from_slice(data);
+        }
 
-        self.unlock_with_key(passcode_key);
+        for entry in layout {
+            match entry {
+                KeybagLayout::Known(tag) => {
+                    if let Some(index) = blocks.iter().position(|(known, _)| known == tag) {
+                        let (tag, data) = blocks.remove(index);
+                        push(out, tag.name(), &data);
+                    }
+                }
+                KeybagLayout::Unknown(name, data) => push(out, name, data),
+            }
+        }
+
+        for (tag, data) in blocks {
+            push(out, tag.name(), &data);
+        }
+    }
+
+    /// Read a big endian u32 value from a block.
+    fn read_u32(block: &BackupKeyBagBlock) -> Result<u32, BackupError> {
+        match KeyBag::get_u8_4(block.data.as_slice()) {
+            Some(bytes) => Ok(u32::from_be_bytes(bytes)),
+            None => Err(BackupError::KeybagInvalidValue(block.tag.clone())),
+        }
+    }
+
+    /// Read a uuid value from a block.
+    fn read_uuid(block: &BackupKeyBagBlock) -> Result<Uuid, BackupError> {
+        Uuid::from_slice(&block.data)
+            .map_err(|_| BackupError::KeybagInvalidValue(block.tag.clone()))
     }
 
-    fn init_keybag(root_blocks: Vec<BackupKeyBagBlock>) -> KeyBag {
+    fn init_keybag(root_blocks: Vec<BackupKeyBagBlock>) -> Result<KeyBag, BackupError> {
         let mut version: Option<u32> = None;
         let mut kind: Option<KeybagTypes> = None;
         let mut uuid: Option<Uuid> = None;
@@ -212,41 +420,33 @@ impl KeyBag {
         let mut dpwt: Option<u32> = None;
         let mut dpic: Option<u32> = None;
         let mut wrap: Option<u32> = None;
+        let mut layout = vec![];
 
         for block in root_blocks {
+            layout.push(KeybagLayout::from_block(&block));
             match block.tag {
                 KeybagBlockTag::UUID => {
-                    uuid = Some(Uuid::parse_str(&hex::encode(&block.data)).unwrap());
+                    uuid = Some(KeyBag::read_uuid(&block)?);
                     debug!("found uuid: {:?}", uuid);
                 }
                 KeybagBlockTag::VERS => {
-                    version = Some(u32::from_be_bytes(
-                        KeyBag::get_u8_4(block.data.as_slice()).unwrap(),
-                    ));
+                    version = Some(KeyBag::read_u32(&block)?);
                     debug!("found version: {:?}", version);
                 }
                 KeybagBlockTag::TYPE => {
-                    kind = Some(KeybagTypes::from(u32::from_be_bytes(
-                        KeyBag::get_u8_4(block.data.as_slice()).unwrap(),
-                    )));
+                    kind = Some(KeybagTypes::from(KeyBag::read_u32(&block)?));
                     debug!("found kind: {:?}", kind);
                 }
                 KeybagBlockTag::ITER => {
-                    iterations = Some(u32::from_be_bytes(
-                        KeyBag::get_u8_4(block.data.as_slice()).unwrap(),
-                    ));
+                    iterations = Some(KeyBag::read_u32(&block)?);
                     debug!("found iterations: {:?}", iterations);
                 }
                 KeybagBlockTag::DPWT => {
-                    dpwt = Some(u32::from_be_bytes(
-                        KeyBag::get_u8_4(block.data.as_slice()).unwrap(),
-                    ));
+                    dpwt = Some(KeyBag::read_u32(&block)?);
                     debug!("found dpwt: {:?}", dpwt);
                 }
                 KeybagBlockTag::DPIC => {
-                    dpic = Some(u32::from_be_bytes(
-                        KeyBag::get_u8_4(block.data.as_slice()).unwrap(),
-                    ));
+                    dpic = Some(KeyBag::read_u32(&block)?);
                     debug!("found dpic: {:?}", dpic);
                 }
                 KeybagBlockTag::DPSL => {
@@ -262,10 +462,8 @@ impl KeyBag {
                     debug!("found hmck: {:?}", hmck);
                 }
                 KeybagBlockTag::WRAP => {
-                    wrap = Some(u32::from_be_bytes(
-                        KeyBag::get_u8_4(block.data.as_slice()).unwrap(),
-                    ));
-                    debug!("found hmck: {:?}", hmck);
+                    wrap = Some(KeyBag::read_u32(&block)?);
+                    debug!("found wrap: {:?}", wrap);
                 }
                 x => {
                     debug!("cannot handle {:?}", x);
@@ -273,85 +471,91 @@ impl KeyBag {
             }
         }
 
-        KeyBag {
-            version: version.unwrap(),
-            uuid: uuid.unwrap(),
-            kind: kind.unwrap(),
-            iterations: iterations,
-            dpwt: dpwt,
-            dpic: dpic,
-            double_protection_salt: double_protection_salt,
-            salt: salt.unwrap(),
-            hmck: hmck.unwrap(),
-            wrap: wrap.unwrap(),
+        Ok(KeyBag {
+            version: version.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::VERS))?,
+            uuid: uuid.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::UUID))?,
+            kind: kind.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::TYPE))?,
+            iterations: Some(
+                iterations.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::ITER))?,
+            ),
+            dpwt,
+            dpic,
+            double_protection_salt,
+            salt: salt.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::SALT))?,
+            hmck: hmck.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::HMCK))?,
+            wrap: wrap.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::WRAP))?,
             keys: vec![],
             key: None,
-        }
+            layout,
+        })
     }
 
-    fn init_container(blocks: &Vec<BackupKeyBagBlock>) -> KeybagEntry {
+    fn init_container(blocks: &Vec<BackupKeyBagBlock>) -> Result<KeybagEntry, BackupError> {
         let mut uuid: Option<Uuid> = None;
         let mut class: Option<ProtectionClass> = None;
         let mut key_type: Option<KeyTypes> = None;
         let mut wrap: Option<u32> = None;
         let mut wpky: Option<Vec<u8>> = None;
+        let mut pbky: Option<Vec<u8>> = None;
+        let mut layout = vec![];
 
         for block in blocks {
+            layout.push(KeybagLayout::from_block(block));
             match block.tag {
                 KeybagBlockTag::UUID => {
-                    uuid = Some(Uuid::parse_str(&hex::encode(&block.data)).unwrap());
+                    uuid = Some(KeyBag::read_uuid(block)?);
                     debug!("found uuid: {:?}", uuid);
                 }
                 KeybagBlockTag::CLAS => {
-                    class = Some(ProtectionClass::from(u32::from_be_bytes(
-                        KeyBag::get_u8_4(block.data.as_slice()).unwrap(),
-                    )));
+                    class = Some(ProtectionClass::from(KeyBag::read_u32(block)?));
                     debug!("found protclass: {:?}", class);
                 }
                 KeybagBlockTag::KTYP => {
-                    key_type = Some(KeyTypes::from(u32::from_be_bytes(
-                        KeyBag::get_u8_4(block.data.as_slice()).unwrap(),
-                    )));
+                    key_type = Some(KeyTypes::from(KeyBag::read_u32(block)?));
                     debug!("found keytype: {:?}", key_type);
                 }
                 KeybagBlockTag::WRAP => {
-                    wrap = Some(u32::from_be_bytes(
-                        KeyBag::get_u8_4(block.data.as_slice()).unwrap(),
-                    ));
+                    wrap = Some(KeyBag::read_u32(block)?);
                     debug!("found wrapper: {:?}", wrap);
                 }
                 KeybagBlockTag::WPKY => {
                     wpky = Some(block.data.clone());
                     debug!("found wpky: {:?}", block.data);
                 }
+                KeybagBlockTag::PBKY => {
+                    pbky = Some(block.data.clone());
+                    debug!("found pbky: {:?}", block.data);
+                }
                 _ => {}
             };
         }
 
-        KeybagEntry {
-            uuid: uuid.unwrap(),
-            class: class.unwrap(),
-            key_type: key_type.unwrap(),
-            wrap: wrap.unwrap(),
-            wpky: wpky.unwrap(),
+        Ok(KeybagEntry {
+            uuid: uuid.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::UUID))?,
+            class: class.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::CLAS))?,
+            key_type: key_type.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::KTYP))?,
+            wrap: wrap.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::WRAP))?,
+            wpky: wpky.ok_or(BackupError::KeybagMissingTag(KeybagBlockTag::WPKY))?,
+            pbky,
             key: None,
-        }
+            layout,
+        })
     }
 
-    pub fn init(data: Vec<u8>) -> KeyBag {
-        let blocks = KeyBag::parse_tlb_blocks(data).unwrap();
+    pub fn init(data: Vec<u8>) -> Result<KeyBag, BackupError> {
+        let blocks = KeyBag::parse_tlb_blocks(data)?;
         let root_blocks = KeyBag::find_root_blocks(&blocks);
         let contained_entries = KeyBag::find_contained_blocks(&blocks);
 
         // debug!("root: {:#?}", root_blocks);
         // debug!("contained: {:#?}", contained_entries);
-        let mut keybag = KeyBag::init_keybag(root_blocks);
+        let mut keybag = KeyBag::init_keybag(root_blocks)?;
         keybag.keys = contained_entries
             .iter()
-            .map(|v| KeyBag::init_container(v))
-            .collect::<Vec<KeybagEntry>>();
+            .map(KeyBag::init_container)
+            .collect::<Result<Vec<KeybagEntry>, BackupError>>()?;
 
-        keybag
+        Ok(keybag)
     }
 
     fn get_u8_4(vec: &[u8]) -> Option<[u8; 4]> {
@@ -362,29 +566,229 @@ impl KeyBag {
         }
     }
 
-    fn parse_tlb_blocks(data: Vec<u8>) -> Option<Vec<BackupKeyBagBlock>> {
+    fn parse_tlb_blocks(data: Vec<u8>) -> Result<Vec<BackupKeyBagBlock>, BackupError> {
         let mut i = 0;
         let mut blocks = vec![];
 
         debug!("parse tlb blocks: {}", data.len());
         while i + 8 < data.len() {
-            let tag = match std::str::from_utf8(&data[i..i + 4]) {
-                Ok(res) => KeybagBlockTag::from(res),
-                Err(err) => panic!("Error parsing key type: {}", err),
+            let name = match std::str::from_utf8(&data[i..i + 4]) {
+                Ok(res) => res.to_string(),
+                Err(_) => return Err(BackupError::KeybagInvalidTag { offset: i }),
             };
+            let tag = KeybagBlockTag::from(name.as_str());
             let x: [u8; 4] = match KeyBag::get_u8_4(&data[i + 4..i + 8]) {
                 Some(el) => el,
-                None => return None,
+                None => {
+                    return Err(BackupError::KeybagTruncated {
+                        offset: i,
+                        length: 0,
+                    })
+                }
             };
             let length = u32::from_be_bytes(x) as usize;
+            if i + 8 + length > data.len() {
+                return Err(BackupError::KeybagTruncated { offset: i, length });
+            }
             let data = Vec::from(&data[i + 8..i + 8 + length]);
 
             debug!("tag: {:?}, length: {}", tag, length);
-            blocks.push(BackupKeyBagBlock { tag, length, data });
+            blocks.push(BackupKeyBagBlock {
+                tag,
+                name,
+                length,
+                data,
+            });
 
             i += 8 + length;
         }
 
-        Some(blocks)
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keybag(salt: &[u8], iterations: u32) -> KeyBag {
+        KeyBag {
+            kind: KeybagTypes::Backup,
+            version: 3,
+            uuid: Uuid::nil(),
+            hmck: vec![],
+            salt: salt.to_vec(),
+            double_protection_salt: None,
+            iterations: Some(iterations),
+            dpwt: None,
+            dpic: None,
+            wrap: 0,
+            keys: vec![],
+            key: None,
+            layout: vec![],
+        }
+    }
+
+    fn block(tag: &str, data: &[u8]) -> Vec<u8> {
+        let mut out = tag.as_bytes().to_vec();
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn test_truncated_block() {
+        let mut data = block("VERS", &3u32.to_be_bytes());
+        data.extend_from_slice(b"SALT\x00\x00\x00\xff\x01\x02");
+        assert!(matches!(
+            KeyBag::init(data),
+            Err(BackupError::KeybagTruncated {
+                offset: 12,
+                length: 255
+            })
+        ));
+    }
+
+    #[test]
+    fn test_missing_tag() {
+        let mut data = block("VERS", &3u32.to_be_bytes());
+        data.extend(block("TYPE", &1u32.to_be_bytes()));
+        data.extend(block("UUID", &[0u8; 16]));
+        assert!(matches!(
+            KeyBag::init(data),
+            Err(BackupError::KeybagMissingTag(KeybagBlockTag::ITER))
+        ));
+    }
+
+    #[test]
+    fn test_contained_blocks() {
+        let mut data = block("VERS", &3u32.to_be_bytes());
+        data.extend(block("UUID", &[0u8; 16]));
+        for class in 1u32..=2 {
+            data.extend(block("UUID", &[class as u8; 16]));
+            data.extend(block("CLAS", &class.to_be_bytes()));
+        }
+
+        let blocks = KeyBag::parse_tlb_blocks(data).unwrap();
+        let sections = KeyBag::find_contained_blocks(&blocks);
+
+        // the last class key isn't followed by a uuid, and must not be dropped
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[1][0].data, vec![2u8; 16]);
+        assert_eq!(sections[1][1].tag, KeybagBlockTag::CLAS);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut data = block("VERS", &4u32.to_be_bytes());
+        data.extend(block("TYPE", &1u32.to_be_bytes()));
+        data.extend(block("UUID", &[1u8; 16]));
+        data.extend(block("HMCK", &[2u8; 40]));
+        data.extend(block("WRAP", &0u32.to_be_bytes()));
+        data.extend(block("SALT", &[3u8; 20]));
+        data.extend(block("ITER", &10000u32.to_be_bytes()));
+        data.extend(block("GRCE", &[4u8; 3]));
+        data.extend(block("DPWT", &1u32.to_be_bytes()));
+        data.extend(block("DPIC", &10000000u32.to_be_bytes()));
+        data.extend(block("DPSL", &[5u8; 20]));
+        for class in 1u32..=3 {
+            data.extend(block("UUID", &[class as u8 + 10; 16]));
+            data.extend(block("CLAS", &class.to_be_bytes()));
+            if class == 2 {
+                data.extend(block("SPEC", &[6u8; 5]));
+            }
+            data.extend(block("WRAP", &3u32.to_be_bytes()));
+            data.extend(block("KTYP", &(class / 3).to_be_bytes()));
+            data.extend(block("WPKY", &[class as u8; 40]));
+            if class == 3 {
+                data.extend(block("PBKY", &[7u8; 32]));
+            }
+        }
+
+        let bag = KeyBag::init(data.clone()).unwrap();
+        assert_eq!(bag.keys.len(), 3);
+        assert_eq!(
+            bag.layout[7],
+            KeybagLayout::Unknown("GRCE".to_string(), vec![4u8; 3])
+        );
+        assert_eq!(bag.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn test_set_passcode() {
+        let mut bag = keybag(b"salt", 2);
+        bag.double_protection_salt = Some(vec![1; 20]);
+        bag.dpic = Some(2);
+        bag.dpwt = Some(1);
+
+        let old_key = bag.derive_passcode_key("old");
+        let class_key = vec![7u8; 32];
+        bag.keys.push(KeybagEntry {
+            uuid: Uuid::nil(),
+            class: ProtectionClass::NSFileProtectionCompleteUntilFirstUserAuthentication,
+            wrap: 2,
+            key_type: KeyTypes::Aes,
+            wpky: crate::crypto::aes::wrap_key(&old_key, &class_key).unwrap(),
+            pbky: None,
+            key: None,
+            layout: vec![],
+        });
+        bag.unlock_with_key(old_key).unwrap();
+
+        bag.set_passcode("new", Some(3), None).unwrap();
+        assert_ne!(bag.salt, b"salt".to_vec());
+
+        let mut reloaded = KeyBag::init(bag.to_bytes().unwrap()).unwrap();
+        assert_eq!(reloaded.salt, bag.salt);
+        assert_eq!(reloaded.iterations, Some(3));
+        assert_eq!(reloaded.dpic, Some(2));
+        assert_eq!(reloaded.double_protection_salt, bag.double_protection_salt);
+        assert!(matches!(
+            reloaded.unlock_with_passcode("old"),
+            Err(BackupError::WrongPassword)
+        ));
+        reloaded.unlock_with_passcode("new").unwrap();
+        assert_eq!(reloaded.keys[0].key, Some(class_key));
+    }
+
+    #[test]
+    fn test_set_passcode_locked() {
+        let mut bag = keybag(b"salt", 2);
+        bag.keys.push(KeybagEntry {
+            uuid: Uuid::nil(),
+            class: ProtectionClass::NSFileProtectionComplete,
+            wrap: 2,
+            key_type: KeyTypes::Aes,
+            wpky: vec![0; 40],
+            pbky: None,
+            key: None,
+            layout: vec![],
+        });
+        assert!(matches!(
+            bag.set_passcode("new", None, None),
+            Err(BackupError::NoClassKey(
+                ProtectionClass::NSFileProtectionComplete
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_set_passcode_dpic_without_dpsl() {
+        let mut bag = keybag(b"salt", 2);
+        assert!(matches!(
+            bag.set_passcode("new", None, Some(10)),
+            Err(BackupError::KeybagMissingTag(KeybagBlockTag::DPSL))
+        ));
+        assert_eq!(bag.dpic, None);
+    }
+
+    #[test]
+    fn test_legacy_derivation() {
+        // RFC 6070 pbkdf2-hmac-sha1 test vector, c = 2
+        let key = keybag(b"salt", 2).derive_passcode_key("password");
+        assert_eq!(
+            hex::encode(&key[0..20]),
+            "ea6c014dc72d6f8ccd1ed92ace1d41f0d8de8957"
+        );
     }
 }
@@ -43,3 +43,26 @@ impl From<&str> for KeybagBlockTag {
         }
     }
 }
+
+impl KeybagBlockTag {
+    /// The four character tag name, `"????"` for `Unknown`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeybagBlockTag::UUID => "UUID",
+            KeybagBlockTag::CLAS => "CLAS",
+            KeybagBlockTag::WRAP => "WRAP",
+            KeybagBlockTag::WPKY => "WPKY",
+            KeybagBlockTag::KTYP => "KTYP",
+            KeybagBlockTag::PBKY => "PBKY",
+            KeybagBlockTag::VERS => "VERS",
+            KeybagBlockTag::TYPE => "TYPE",
+            KeybagBlockTag::HMCK => "HMCK",
+            KeybagBlockTag::SALT => "SALT",
+            KeybagBlockTag::ITER => "ITER",
+            KeybagBlockTag::DPWT => "DPWT",
+            KeybagBlockTag::DPIC => "DPIC",
+            KeybagBlockTag::DPSL => "DPSL",
+            KeybagBlockTag::Unknown => "????",
+        }
+    }
+}
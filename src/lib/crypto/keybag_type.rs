@@ -20,3 +20,16 @@ impl From<u32> for KeybagTypes {
         }
     }
 }
+
+impl KeybagTypes {
+    /// The value stored in the keybag's `TYPE` tag, None for `Unknown`.
+    pub fn value(&self) -> Option<u32> {
+        match self {
+            KeybagTypes::System => Some(0),
+            KeybagTypes::Backup => Some(1),
+            KeybagTypes::Escrow => Some(2),
+            KeybagTypes::iCloud => Some(3),
+            KeybagTypes::Unknown => None,
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use ::plist::{Dictionary, Value};
+use uuid::Uuid;
+
+use crate::crypto::{unwrap_key, wrap_key};
+use crate::error::BackupError;
+
+/// Version of the key file format
+const KEY_FILE_VERSION: u64 = 1;
+
+/// A cache of derived passcode keys, so the slow key derivation only runs once per backup.
+///
+/// Keys are stored by keybag uuid, RFC 3394 wrapped with a local secret. The key
+/// file is useless without the secret, which never has to leave the machine.
+#[derive(Debug, Default)]
+pub struct KeyFile {
+    keys: BTreeMap<Uuid, Vec<u8>>,
+}
+
+impl KeyFile {
+    /// Load a key file, or start an empty one if it doesn't exist yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<KeyFile, BackupError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(KeyFile::default());
+        }
+
+        let value = Value::from_file(path)?;
+        let dict = value
+            .as_dictionary()
+            .ok_or(BackupError::InvalidKeyFile("not a dictionary"))?;
+
+        match dict.get("Version").and_then(|v| v.as_unsigned_integer()) {
+            Some(KEY_FILE_VERSION) => {}
+            _ => return Err(BackupError::InvalidKeyFile("unsupported version")),
+        }
+
+        let entries = dict
+            .get("Keys")
+            .and_then(|v| v.as_dictionary())
+            .ok_or(BackupError::InvalidKeyFile("missing keys"))?;
+
+        let mut keys = BTreeMap::new();
+        for (uuid, wrapped) in entries {
+            let uuid =
+                Uuid::parse_str(uuid).map_err(|_| BackupError::InvalidKeyFile("invalid uuid"))?;
+            let wrapped = wrapped
+                .as_data()
+                .ok_or(BackupError::InvalidKeyFile("key is not data"))?;
+            keys.insert(uuid, wrapped.to_vec());
+        }
+
+        Ok(KeyFile { keys })
+    }
+
+    /// Write the key file, readable only by the current user.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), BackupError> {
+        let mut entries = Dictionary::new();
+        for (uuid, wrapped) in &self.keys {
+            entries.insert(uuid.to_string(), Value::Data(wrapped.clone()));
+        }
+
+        let mut dict = Dictionary::new();
+        dict.insert("Version".into(), Value::Integer(KEY_FILE_VERSION.into()));
+        dict.insert("Keys".into(), Value::Dictionary(entries));
+
+        let mut out: Vec<u8> = vec![];
+        Value::Dictionary(dict).to_writer_xml(&mut out)?;
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        options.open(path)?.write_all(&out)?;
+
+        Ok(())
+    }
+
+    /// Find the passcode key for a keybag.
+    /// Fails with `BackupError::WrongPassword` if the entry was wrapped with another secret.
+    pub fn get(&self, uuid: &Uuid, secret: &[u8]) -> Result<Option<Vec<u8>>, BackupError> {
+        match self.keys.get(uuid) {
+            Some(wrapped) => Ok(Some(unwrap_key(secret, wrapped)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store the passcode key for a keybag, replacing any previous entry.
+    pub fn insert(&mut self, uuid: Uuid, key: &[u8], secret: &[u8]) -> Result<(), BackupError> {
+        self.keys.insert(uuid, wrap_key(secret, key)?);
+        Ok(())
+    }
+
+    /// Forget the passcode key for a keybag, returns true if there was one.
+    pub fn remove(&mut self, uuid: &Uuid) -> bool {
+        self.keys.remove(uuid).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys.plist");
+        let uuid = Uuid::new_v4();
+        let secret = [3u8; 32];
+        let key = [5u8; 32];
+
+        let mut keyfile = KeyFile::load(&path).unwrap();
+        assert_eq!(keyfile.get(&uuid, &secret).unwrap(), None);
+        keyfile.insert(uuid, &key, &secret).unwrap();
+        keyfile.save(&path).unwrap();
+
+        let keyfile = KeyFile::load(&path).unwrap();
+        assert_eq!(keyfile.get(&uuid, &secret).unwrap(), Some(key.to_vec()));
+        assert!(matches!(
+            keyfile.get(&uuid, &[4u8; 32]),
+            Err(BackupError::WrongPassword)
+        ));
+    }
+}
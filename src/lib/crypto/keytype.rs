@@ -14,3 +14,14 @@ impl From<u32> for KeyTypes {
         }
     }
 }
+
+impl KeyTypes {
+    /// The value stored in a class key's `KTYP` tag, None for `Unknown`.
+    pub fn value(&self) -> Option<u32> {
+        match self {
+            KeyTypes::Aes => Some(0),
+            KeyTypes::Curve25519 => Some(1),
+            KeyTypes::Unknown => None,
+        }
+    }
+}
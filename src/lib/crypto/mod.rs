@@ -1,13 +1,17 @@
 mod aes;
+mod decrypt_reader;
 mod keybag;
 mod keybag_block;
 mod keybag_type;
+mod keyfile;
 mod keytype;
 mod protectionclass;
 
 pub use self::aes::*;
+pub use decrypt_reader::DecryptReader;
 pub use keybag::*;
 pub use keybag_block::*;
 pub use keybag_type::*;
+pub use keyfile::KeyFile;
 pub use keytype::*;
 pub use protectionclass::*;
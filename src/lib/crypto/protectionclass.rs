@@ -1,7 +1,9 @@
 #![allow(non_camel_case_types)]
 
+use serde::Serialize;
+
 /// https://stackoverflow.com/questions/1498342/how-to-decrypt-an-encrypted-apple-itunes-iphone-backup
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone, Serialize)]
 pub enum ProtectionClass {
     NSFileProtectionComplete,
     NSFileProtectionCompleteUnlessOpen,
@@ -0,0 +1,352 @@
+//! Finding backups on disk, and looking them up by device.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::backup::Backup;
+use crate::error::BackupError;
+
+/// Environment variable with extra directories to search for backups,
+/// separated like `PATH`. Replaces the default directories when set.
+pub const BACKUP_DIRS_ENV: &str = "IBACKUPTOOL_BACKUP_DIRS";
+
+/// How many directories deep below a root backups are searched for.
+const MAX_DEPTH: usize = 8;
+
+/// Directories backups are looked for in, unless others are given.
+///
+/// This is `IBACKUPTOOL_BACKUP_DIRS` if it is set, otherwise where iTunes and Finder
+/// store backups: `~/Library/Application Support/MobileSync/Backup` on macOS, and
+/// `%APPDATA%\Apple Computer\MobileSync\Backup` or `%USERPROFILE%\Apple\MobileSync\Backup`
+/// on Windows. On Linux, where backups are made with `idevicebackup2`, this is
+/// `$XDG_DATA_HOME/MobileSync/Backup` (`~/.local/share` if unset).
+pub fn default_roots() -> Vec<PathBuf> {
+    if let Some(dirs) = std::env::var_os(BACKUP_DIRS_ENV) {
+        return std::env::split_paths(&dirs).collect();
+    }
+
+    let mut roots = vec![];
+    if let Some(home) = dirs::home_dir() {
+        roots.push(home.join("Library/Application Support/MobileSync/Backup"));
+
+        if cfg!(windows) {
+            roots.push(home.join("Apple").join("MobileSync").join("Backup"));
+        }
+    }
+
+    if cfg!(windows) {
+        if let Some(appdata) = dirs::data_dir() {
+            roots.push(
+                appdata
+                    .join("Apple Computer")
+                    .join("MobileSync")
+                    .join("Backup"),
+            );
+        }
+    }
+
+    if cfg!(target_os = "linux") {
+        if let Some(data) = dirs::data_dir() {
+            roots.push(data.join("MobileSync").join("Backup"));
+        }
+    }
+
+    roots
+}
+
+/// Check if a directory is a backup, by its `Manifest.plist` and `Info.plist`.
+pub fn is_backup_dir(path: &Path) -> bool {
+    path.join("Manifest.plist").is_file() && path.join("Info.plist").is_file()
+}
+
+/// Find every backup directory below the roots, sorted by path.
+/// Roots that don't exist are skipped, and backups are not searched for inside of backups.
+pub fn discover(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut found = vec![];
+    let mut visited = HashSet::new();
+
+    for root in roots {
+        if !root.is_dir() {
+            debug!("skipping backup directory {}: not found", root.display());
+            continue;
+        }
+
+        scan(root, MAX_DEPTH, &mut visited, &mut found);
+    }
+
+    found.sort();
+    found
+}
+
+fn scan(dir: &Path, depth: usize, visited: &mut HashSet<PathBuf>, found: &mut Vec<PathBuf>) {
+    // symlinked backup folders are common, so links are followed, but only once
+    match dir.canonicalize() {
+        Ok(real) if !visited.contains(&real) => visited.insert(real),
+        _ => return,
+    };
+
+    if is_backup_dir(dir) {
+        found.push(dir.to_path_buf());
+        return;
+    }
+
+    if depth == 0 {
+        return;
+    }
+
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            debug!("can't read {}: {}", dir.display(), err);
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            scan(&path, depth - 1, visited, found);
+        }
+    }
+}
+
+/// When the backup was made, from `Status.plist`, or else when that file was written.
+pub fn backup_date(backup: &Backup) -> Option<DateTime<Utc>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(&backup.status.date) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    let modified = std::fs::metadata(backup.path.join("Status.plist"))
+        .and_then(|meta| meta.modified())
+        .ok()?;
+    Some(DateTime::<Utc>::from(modified))
+}
+
+/// Check if a backup belongs to the device named by `query`: its device name or
+/// serial number (ignoring case), or a prefix of its UDID or directory name.
+fn matches_device(path: &Path, backup: &Backup, query: &str) -> bool {
+    let query = query.to_lowercase();
+    let info = &backup.info;
+    let lockdown = &backup.manifest.lockdown;
+
+    let names = [info.device_name.as_deref(), Some(&lockdown.device_name)];
+    let serials = [info.serial_number.as_deref(), Some(&lockdown.serial_number)];
+    let dir_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string());
+    let ids = [
+        Some(info.target_identifier.as_str()),
+        info.unique_identifier.as_deref(),
+        Some(&lockdown.unique_device_id),
+        dir_name.as_deref(),
+    ];
+
+    names
+        .iter()
+        .chain(serials.iter())
+        .flatten()
+        .any(|value| value.to_lowercase() == query)
+        || ids
+            .iter()
+            .flatten()
+            .any(|id| id.to_lowercase().starts_with(&query))
+}
+
+/// Find the backup named by `query`, which is one of
+///
+/// - a path to a backup directory, zip file or tarball
+/// - the name of a backup directory in one of the roots, usually the device UDID
+/// - `latest`, for the most recently made backup
+/// - a device name, serial number or UDID prefix
+///
+/// Returns `BackupNotFound` if nothing matches, and `AmbiguousBackup` if a device
+/// matches more than one backup.
+pub fn find_backup(roots: &[PathBuf], query: &str) -> Result<PathBuf, BackupError> {
+    let path = Path::new(query);
+    if path.exists() {
+        return Ok(path.to_path_buf());
+    }
+
+    if let Some(path) = roots
+        .iter()
+        .map(|root| root.join(query))
+        .find(|path| path.exists())
+    {
+        return Ok(path);
+    }
+
+    let backups = discover(roots)
+        .into_iter()
+        .filter_map(|path| match Backup::new(&path) {
+            Ok(backup) => Some((path, backup)),
+            Err(err) => {
+                debug!("skipping {}: {}", path.display(), err);
+                None
+            }
+        });
+
+    if query == "latest" {
+        return backups
+            .max_by_key(|(_, backup)| backup_date(backup))
+            .map(|(path, _)| path)
+            .ok_or_else(|| BackupError::BackupNotFound(query.to_string()));
+    }
+
+    let found: Vec<PathBuf> = backups
+        .filter(|(path, backup)| matches_device(path, backup, query))
+        .map(|(path, _)| path)
+        .collect();
+
+    match found.len() {
+        0 => Err(BackupError::BackupNotFound(query.to_string())),
+        1 => Ok(found.into_iter().next().unwrap()),
+        _ => Err(BackupError::AmbiguousBackup(found)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write the plists of a minimal backup.
+    fn write_backup(dir: &Path, name: &str, serial: &str, udid: &str, date: &str) {
+        std::fs::create_dir_all(dir).unwrap();
+
+        let plist = |entries: &[(&str, &str)]| {
+            let mut dict = plist::Dictionary::new();
+            for (key, value) in entries {
+                dict.insert(key.to_string(), plist::Value::String(value.to_string()));
+            }
+            dict
+        };
+
+        let info = plist(&[
+            ("Device Name", name),
+            ("Serial Number", serial),
+            ("Product Type", "iPhone12,1"),
+            ("Product Version", "14.2"),
+            ("Target Identifier", udid),
+            ("Target Type", "Device"),
+        ]);
+        let mut status = plist(&[
+            ("BackupState", "new"),
+            ("Date", date),
+            ("SnapshotState", "finished"),
+            ("UUID", "U-1"),
+            ("Version", "3.3"),
+        ]);
+        status.insert("IsFullBackup".to_string(), plist::Value::Boolean(false));
+        let mut manifest = plist(&[
+            ("Version", "10.0"),
+            ("Date", date),
+            ("SystemDomainsVersion", "24.0"),
+        ]);
+        manifest.insert("IsEncrypted".to_string(), plist::Value::Boolean(false));
+        manifest.insert("WasPasscodeSet".to_string(), plist::Value::Boolean(false));
+        manifest.insert(
+            "Lockdown".to_string(),
+            plist::Value::Dictionary(plist(&[
+                ("ProductVersion", "14.2"),
+                ("ProductType", "iPhone12,1"),
+                ("UniqueDeviceID", udid),
+                ("SerialNumber", serial),
+                ("DeviceName", name),
+            ])),
+        );
+
+        for (file, dict) in &[
+            ("Info.plist", info),
+            ("Status.plist", status),
+            ("Manifest.plist", manifest),
+        ] {
+            plist::Value::Dictionary(dict.clone())
+                .to_file_xml(dir.join(file))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_find_backup() {
+        let root = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let first = root.path().join("00008030-AAAA");
+        let second = root.path().join("nested/evidence/00008101-BBBB");
+        let third = other.path().join("00008101-CCCC");
+        write_backup(
+            &first,
+            "Work Phone",
+            "F2LAAA",
+            "00008030-AAAA",
+            "2020-01-01T00:00:00Z",
+        );
+        write_backup(
+            &second,
+            "iPhone",
+            "F2LBBB",
+            "00008101-BBBB",
+            "2021-06-01T00:00:00Z",
+        );
+        write_backup(
+            &third,
+            "iPhone",
+            "F2LCCC",
+            "00008101-CCCC",
+            "2020-06-01T00:00:00Z",
+        );
+        // backups inside of backups are not searched for
+        write_backup(
+            &first.join("inner"),
+            "Inner",
+            "X",
+            "X",
+            "2022-01-01T00:00:00Z",
+        );
+
+        let roots = vec![root.path().to_path_buf(), other.path().to_path_buf()];
+        let mut expected = vec![first.clone(), second.clone(), third.clone()];
+        expected.sort();
+        assert_eq!(discover(&roots), expected);
+
+        assert_eq!(find_backup(&roots, "00008030-AAAA").unwrap(), first);
+        assert_eq!(find_backup(&roots, "work phone").unwrap(), first);
+        assert_eq!(find_backup(&roots, "f2lbbb").unwrap(), second);
+        assert_eq!(find_backup(&roots, "00008101-c").unwrap(), third);
+        assert_eq!(find_backup(&roots, "latest").unwrap(), second);
+
+        assert!(matches!(
+            find_backup(&roots, "iPhone"),
+            Err(BackupError::AmbiguousBackup(paths)) if paths.len() == 2
+        ));
+        assert!(matches!(
+            find_backup(&roots, "Android"),
+            Err(BackupError::BackupNotFound(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_default_roots_linux() {
+        let saved: Vec<(&str, Option<std::ffi::OsString>)> = [BACKUP_DIRS_ENV, "XDG_DATA_HOME"]
+            .iter()
+            .map(|name| (*name, std::env::var_os(name)))
+            .collect();
+
+        std::env::remove_var(BACKUP_DIRS_ENV);
+        std::env::set_var("XDG_DATA_HOME", "/data");
+        let roots = default_roots();
+        assert!(roots.contains(&PathBuf::from("/data/MobileSync/Backup")));
+
+        std::env::set_var(BACKUP_DIRS_ENV, "/a:/b");
+        let roots = default_roots();
+
+        for (name, value) in saved {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+        assert_eq!(roots, vec![PathBuf::from("/a"), PathBuf::from("/b")]);
+    }
+}
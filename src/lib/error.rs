@@ -1,9 +1,70 @@
-#[derive(Debug, Clone, Copy)]
+use crate::crypto::{KeybagBlockTag, ProtectionClass};
+
+#[derive(Debug)]
 pub enum BackupError {
     InManifestButNotFound,
     NoFileInfo,
     NoEncryptionKey,
     FileNotFound,
+    WrongPassword,
+    /// The decrypted size of a file doesn't match the size in the manifest
+    SizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+
+    /// The backup is encrypted, but has no keybag (or it hasn't been parsed)
+    NoKeybag,
+    /// The keybag has no unwrapped key for this protection class
+    NoClassKey(ProtectionClass),
+    /// A wrapped key is not a valid RFC 3394 blob
+    InvalidWrappedKey,
+
+    /// A keybag block tag at this offset is not valid utf-8
+    KeybagInvalidTag {
+        offset: usize,
+    },
+    /// A keybag block at this offset claims a length past the end of the keybag
+    KeybagTruncated {
+        offset: usize,
+        length: usize,
+    },
+    /// A tag required to build the keybag is missing
+    KeybagMissingTag(KeybagBlockTag),
+    /// A tag has a value that can't be decoded, e.g. a short integer
+    KeybagInvalidValue(KeybagBlockTag),
+
+    /// The file is not a Manifest.mbdb, or is an unsupported version of it
+    InvalidMbdbHeader,
+    /// The mbdb record starting at this offset is truncated
+    MalformedMbdb {
+        offset: usize,
+    },
+
+    /// A plist is not a valid NSKeyedArchiver archive
+    InvalidKeyedArchive(&'static str),
+    /// The archived file record for this file id could not be decoded
+    InvalidFileInfo {
+        fileid: String,
+        source: Box<BackupError>,
+    },
+    /// The directory or archive does not contain a Manifest.plist
+    RootNotFound,
+    /// A key file can't be read
+    InvalidKeyFile(&'static str),
+    /// A file filter pattern is not a valid glob or regex
+    InvalidFilter(String),
+    /// The backup is stored somewhere it can't be modified in place, like a zip file
+    ReadOnlyBacking,
+    /// No backup matches the path, device name, serial number or UDID
+    BackupNotFound(String),
+    /// More than one backup matches the device name, serial number or UDID
+    AmbiguousBackup(Vec<std::path::PathBuf>),
+
+    Io(std::io::Error),
+    Plist(plist::Error),
+    Sqlite(rusqlite::Error),
+    Zip(zip::result::ZipError),
 }
 
 impl std::fmt::Display for BackupError {
@@ -11,14 +72,113 @@ impl std::fmt::Display for BackupError {
         &self,
         formatter: &mut std::fmt::Formatter<'_>,
     ) -> std::result::Result<(), std::fmt::Error> {
-        write!(formatter, "{:?}", self)
+        match self {
+            BackupError::InManifestButNotFound => {
+                write!(formatter, "file is missing from the backup")
+            }
+            BackupError::WrongPassword => write!(formatter, "wrong backup password"),
+            BackupError::SizeMismatch { expected, actual } => write!(
+                formatter,
+                "decrypted size {} does not match the manifest size {}",
+                actual, expected
+            ),
+            BackupError::NoKeybag => write!(formatter, "backup has no keybag"),
+            BackupError::NoClassKey(class) => {
+                write!(formatter, "keybag has no key for class {:?}", class)
+            }
+            BackupError::InvalidWrappedKey => write!(formatter, "invalid wrapped key"),
+            BackupError::KeybagInvalidTag { offset } => {
+                write!(formatter, "keybag: invalid tag at offset {}", offset)
+            }
+            BackupError::KeybagTruncated { offset, length } => write!(
+                formatter,
+                "keybag: block at offset {} with length {} is truncated",
+                offset, length
+            ),
+            BackupError::KeybagMissingTag(tag) => {
+                write!(formatter, "keybag: missing required tag {:?}", tag)
+            }
+            BackupError::KeybagInvalidValue(tag) => {
+                write!(formatter, "keybag: invalid value for tag {:?}", tag)
+            }
+            BackupError::InvalidMbdbHeader => write!(formatter, "invalid Manifest.mbdb header"),
+            BackupError::MalformedMbdb { offset } => write!(
+                formatter,
+                "Manifest.mbdb: truncated record at offset {}",
+                offset
+            ),
+            BackupError::InvalidKeyedArchive(reason) => {
+                write!(formatter, "invalid NSKeyedArchiver archive: {}", reason)
+            }
+            BackupError::InvalidFileInfo { fileid, source } => {
+                write!(formatter, "invalid file info for {}: {}", fileid, source)
+            }
+            BackupError::RootNotFound => write!(
+                formatter,
+                "could not find a Manifest.plist in the backup. Is this actually a backup?"
+            ),
+            BackupError::InvalidKeyFile(reason) => {
+                write!(formatter, "invalid key file: {}", reason)
+            }
+            BackupError::InvalidFilter(reason) => write!(formatter, "invalid filter: {}", reason),
+            BackupError::ReadOnlyBacking => {
+                write!(formatter, "the backup can't be modified in place")
+            }
+            BackupError::BackupNotFound(query) => {
+                write!(formatter, "no backup found for {}", query)
+            }
+            BackupError::AmbiguousBackup(paths) => write!(
+                formatter,
+                "more than one backup matches: {}",
+                paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            BackupError::Io(err) => write!(formatter, "io error: {}", err),
+            BackupError::Plist(err) => write!(formatter, "plist error: {}", err),
+            BackupError::Sqlite(err) => write!(formatter, "sqlite error: {}", err),
+            BackupError::Zip(err) => write!(formatter, "zip error: {}", err),
+            _ => write!(formatter, "{:?}", self),
+        }
     }
 }
 
 // This is important for other errors to wrap this one.
 impl std::error::Error for BackupError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        // Generic error, underlying cause isn't tracked.
-        None
+        match self {
+            BackupError::InvalidFileInfo { source, .. } => Some(source.as_ref()),
+            BackupError::Io(err) => Some(err),
+            BackupError::Plist(err) => Some(err),
+            BackupError::Sqlite(err) => Some(err),
+            BackupError::Zip(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BackupError {
+    fn from(err: std::io::Error) -> BackupError {
+        BackupError::Io(err)
+    }
+}
+
+impl From<plist::Error> for BackupError {
+    fn from(err: plist::Error) -> BackupError {
+        BackupError::Plist(err)
+    }
+}
+
+impl From<rusqlite::Error> for BackupError {
+    fn from(err: rusqlite::Error) -> BackupError {
+        BackupError::Sqlite(err)
+    }
+}
+
+impl From<zip::result::ZipError> for BackupError {
+    fn from(err: zip::result::ZipError) -> BackupError {
+        BackupError::Zip(err)
     }
 }
@@ -1,11 +1,40 @@
+//! Library for reading iOS backups.
+//!
+//! ```no_run
+//! use ibackuptool2::Backup;
+//! use std::io::Read;
+//!
+//! let backup = Backup::open("/path/to/backup")?.unlock("password")?;
+//! for file in backup.files() {
+//!     println!("{}: {}, {}", file.fileid, file.domain, file.relative_filename);
+//! }
+//!
+//! if let Some(file) = backup.find_path("HomeDomain", "Library/SMS/sms.db") {
+//!     let mut contents = vec![];
+//!     backup.open_file(&file)?.read_to_end(&mut contents)?;
+//! }
+//! # Ok::<(), ibackuptool2::BackupError>(())
+//! ```
+
+#[macro_use]
+extern crate log;
+
 mod backup;
 mod crypto;
+pub mod discover;
 mod error;
 mod plist;
 mod util;
 
-pub use self::backup::*;
-pub use self::crypto::*;
-pub use self::error::*;
-pub use self::plist::*;
-pub use self::util::*;
+pub use self::backup::{
+    open_backing, Access, Backup, BackupBacking, BackupFile, BackupInfo, BackupManifest,
+    BackupManifestLockdown, BackupStatus, DirectoryBacking, FileFilter, FileFlags, FileInfo,
+    FileKind, FileMode, ManifestFormat, MemoryBacking, Quarantine, ReadSeek, TarBacking,
+    TarCompression, VerifyIssue, VerifyProblem, VerifyReport, ZipBacking, QUARANTINE_ATTRIBUTE,
+    WHERE_FROMS_ATTRIBUTE,
+};
+pub use self::crypto::{
+    KeyBag, KeyFile, KeyTypes, KeybagBlockTag, KeybagEntry, KeybagLayout, KeybagTypes,
+    ProtectionClass,
+};
+pub use self::error::BackupError;
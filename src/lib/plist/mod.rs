@@ -1,6 +1,7 @@
+use crate::error::BackupError;
 use plist::Value;
 
-pub fn decode_nskeyedarchiver(value: plist::Value) -> plist::Value {
+pub fn decode_nskeyedarchiver(value: plist::Value) -> Result<plist::Value, BackupError> {
     let mut rot = plist::Dictionary::new();
 
     // First, ensure the top-level is a dictionary.
@@ -8,7 +9,9 @@ pub fn decode_nskeyedarchiver(value: plist::Value) -> plist::Value {
         // Next, ensure that this item is actually created by NSKeyedArchiver
         if let Some(Value::String(string)) = root.get("$archiver") {
             if string != "NSKeyedArchiver" {
-                panic!("not built by NSKeyedArchiver - bailing.");
+                return Err(BackupError::InvalidKeyedArchive(
+                    "not built by NSKeyedArchiver",
+                ));
             }
         }
 
@@ -24,12 +27,15 @@ pub fn decode_nskeyedarchiver(value: plist::Value) -> plist::Value {
         // Try to get the object container
         let objects = match root.get("$objects") {
             Some(Value::Array(objs)) => objs,
-            _ => panic!("no objects!"),
+            _ => return Err(BackupError::InvalidKeyedArchive("no $objects")),
         };
 
         // If we have a root uuid try to get it
         if let Some(root_uid) = top_uid {
-            let root = &objects[root_uid];
+            let root = match objects.get(root_uid) {
+                Some(root) => root,
+                None => return Err(BackupError::InvalidKeyedArchive("root uid out of bounds")),
+            };
             // read referenced object as dict
             if let Some(dict) = root.as_dictionary() {
                 // for each key, unwrap it into it's referenced uid object or self.
@@ -37,7 +43,14 @@ pub fn decode_nskeyedarchiver(value: plist::Value) -> plist::Value {
                     match v {
                         Value::Uid(uid) => {
                             let uid = uid.get() as usize;
-                            let referenced = &objects[uid];
+                            let referenced = match objects.get(uid) {
+                                Some(referenced) => referenced,
+                                None => {
+                                    return Err(BackupError::InvalidKeyedArchive(
+                                        "uid out of bounds",
+                                    ))
+                                }
+                            };
                             rot.insert(k.to_string(), referenced.clone());
                         }
                         _ => {
@@ -47,11 +60,116 @@ pub fn decode_nskeyedarchiver(value: plist::Value) -> plist::Value {
                 }
             }
         } else {
-            panic!("no root uid specified!");
+            return Err(BackupError::InvalidKeyedArchive("no root uid specified"));
         }
     } else {
-        panic!("malformed keyedarchiver - root is not dict.")
+        return Err(BackupError::InvalidKeyedArchive("root is not a dictionary"));
     }
 
-    Value::Dictionary(rot)
+    Ok(Value::Dictionary(rot))
+}
+
+/// Remove a key from the root object of an NSKeyedArchiver archive, in place.
+/// The object it referenced is left in `$objects`, so every other uid stays valid.
+/// Returns whether the key was present.
+pub fn remove_archived_key(value: &mut plist::Value, key: &str) -> Result<bool, BackupError> {
+    let root = match value {
+        Value::Dictionary(root) => root,
+        _ => return Err(BackupError::InvalidKeyedArchive("root is not a dictionary")),
+    };
+
+    let top_uid = match root.get("$top") {
+        Some(Value::Dictionary(dict)) => match dict.get("root") {
+            Some(Value::Uid(val)) => val.get() as usize,
+            _ => return Err(BackupError::InvalidKeyedArchive("no root uid specified")),
+        },
+        _ => return Err(BackupError::InvalidKeyedArchive("no root uid specified")),
+    };
+
+    match root.get_mut("$objects") {
+        Some(Value::Array(objects)) => match objects.get_mut(top_uid) {
+            Some(Value::Dictionary(dict)) => Ok(dict.remove(key).is_some()),
+            Some(_) => Err(BackupError::InvalidKeyedArchive("root is not a dictionary")),
+            None => Err(BackupError::InvalidKeyedArchive("root uid out of bounds")),
+        },
+        _ => Err(BackupError::InvalidKeyedArchive("no $objects")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(top: u64, objects: Vec<Value>) -> Value {
+        let mut top_dict = plist::Dictionary::new();
+        top_dict.insert("root".to_string(), Value::Uid(plist::Uid::new(top)));
+
+        let mut root = plist::Dictionary::new();
+        root.insert(
+            "$archiver".to_string(),
+            Value::String("NSKeyedArchiver".to_string()),
+        );
+        root.insert("$top".to_string(), Value::Dictionary(top_dict));
+        root.insert("$objects".to_string(), Value::Array(objects));
+        Value::Dictionary(root)
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut file = plist::Dictionary::new();
+        file.insert("Size".to_string(), Value::Integer(10.into()));
+        file.insert("RelativePath".to_string(), Value::Uid(plist::Uid::new(2)));
+
+        let decoded = decode_nskeyedarchiver(archive(
+            1,
+            vec![
+                Value::String("$null".to_string()),
+                Value::Dictionary(file),
+                Value::String("Library/SMS/sms.db".to_string()),
+            ],
+        ))
+        .unwrap();
+
+        let dict = decoded.as_dictionary().unwrap();
+        assert_eq!(dict.get("Size").unwrap().as_unsigned_integer(), Some(10));
+        assert_eq!(
+            dict.get("RelativePath").unwrap().as_string(),
+            Some("Library/SMS/sms.db")
+        );
+    }
+
+    #[test]
+    fn test_remove_archived_key() {
+        let mut file = plist::Dictionary::new();
+        file.insert("Size".to_string(), Value::Integer(10.into()));
+        file.insert("EncryptionKey".to_string(), Value::Uid(plist::Uid::new(2)));
+
+        let mut value = archive(
+            1,
+            vec![
+                Value::String("$null".to_string()),
+                Value::Dictionary(file),
+                Value::Data(vec![1, 2, 3]),
+            ],
+        );
+        assert!(remove_archived_key(&mut value, "EncryptionKey").unwrap());
+        assert!(!remove_archived_key(&mut value, "EncryptionKey").unwrap());
+
+        let decoded = decode_nskeyedarchiver(value).unwrap();
+        let dict = decoded.as_dictionary().unwrap();
+        assert!(dict.get("EncryptionKey").is_none());
+        assert_eq!(dict.get("Size").unwrap().as_unsigned_integer(), Some(10));
+    }
+
+    #[test]
+    fn test_decode_out_of_bounds() {
+        assert!(matches!(
+            decode_nskeyedarchiver(archive(5, vec![])),
+            Err(BackupError::InvalidKeyedArchive(_))
+        ));
+        assert!(matches!(
+            decode_nskeyedarchiver(Value::Boolean(true)),
+            Err(BackupError::InvalidKeyedArchive(_))
+        ));
+    }
 }
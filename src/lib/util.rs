@@ -1,3 +1,8 @@
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Serializer;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
 pub fn pack_u64(val: u64) -> [u8; 8] {
     return [
         ((val & 0xFF00000000000000) >> (4 * 14)) as u8,
@@ -39,8 +44,37 @@ pub fn as_u32_be(array: &[u8]) -> u32 {
         + ((array[3] as u32) << 0)
 }
 
+/// Convert seconds since the unix epoch to a date.
+/// Returns None if the date is out of range, which happens with corrupt records.
+pub fn unix_date(secs: u64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(i64::try_from(secs).ok()?, 0).single()
+}
+
+/// Serialize a map of byte values with the values as hex strings, sorted by key.
+pub fn serialize_hex_map<S: Serializer>(
+    map: &HashMap<String, Vec<u8>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut entries: Vec<(&String, String)> = map
+        .iter()
+        .map(|(key, value)| (key, hex::encode(value)))
+        .collect();
+    entries.sort();
+    serializer.collect_map(entries)
+}
+
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn test_unix_date() {
+        assert_eq!(
+            super::unix_date(1577934245).map(|date| date.to_rfc3339()),
+            Some("2020-01-02T03:04:05+00:00".to_string())
+        );
+        assert_eq!(super::unix_date(0xffffffffffff), None);
+        assert_eq!(super::unix_date(u64::MAX), None);
+    }
+
     #[test]
     fn test_u32_read() {
         assert_eq!(
@@ -0,0 +1,115 @@
+use crate::output::Row;
+use ibackuptool2::*;
+use serde_json::{json, Map, Value};
+use std::path::Path;
+
+/// Columns of `ls` in csv and table output
+pub const BACKUP_COLUMNS: [&str; 9] = [
+    "id",
+    "name",
+    "product",
+    "ios",
+    "encrypted",
+    "size",
+    "last_backup",
+    "path",
+    "error",
+];
+
+/// Columns of `ls-files` in csv and table output
+pub const FILE_COLUMNS: [&str; 7] = [
+    "fileid",
+    "domain",
+    "relative_filename",
+    "kind",
+    "size",
+    "mode",
+    "modified",
+];
+
+/// Total size of the files below `path`, or of `path` itself if it is a file.
+fn disk_size(path: &Path) -> std::io::Result<u64> {
+    let meta = std::fs::metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += disk_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+fn to_value<T: serde::Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+/// A backup as a listing row. Backups that fail to load get a row with the error.
+pub fn backup_row(path: &Path) -> Row {
+    let backup = match Backup::new(path) {
+        Ok(backup) => backup,
+        Err(err) => {
+            let record = json!({
+                "path": path.display().to_string(),
+                "error": err.to_string(),
+            });
+            let columns = record.as_object().cloned().unwrap_or_default();
+            return Row { record, columns };
+        }
+    };
+
+    let size = disk_size(path).ok();
+    let last_backup = discover::backup_date(&backup).map(|date| date.to_rfc3339());
+
+    let mut columns = Map::new();
+    columns.insert("id".into(), json!(backup.info.target_identifier));
+    columns.insert("name".into(), json!(backup.info.device_name));
+    columns.insert("product".into(), json!(backup.info.product_name));
+    columns.insert("ios".into(), json!(backup.info.product_version));
+    columns.insert("encrypted".into(), json!(backup.manifest.is_encrypted));
+    columns.insert("size".into(), json!(size));
+    columns.insert("last_backup".into(), json!(last_backup));
+    columns.insert("path".into(), json!(path.display().to_string()));
+    columns.insert("error".into(), Value::Null);
+
+    let record = json!({
+        "path": path.display().to_string(),
+        "size": size,
+        "last_backup": last_backup,
+        "encrypted": backup.manifest.is_encrypted,
+        "error": null,
+        "info": to_value(&backup.info),
+        "status": to_value(&backup.status),
+        "manifest": to_value(&backup.manifest),
+    });
+
+    Row { record, columns }
+}
+
+/// A file in the manifest as a listing row.
+pub fn file_row(file: &BackupFile) -> Row {
+    let info = file.fileinfo.as_ref();
+
+    let mut columns = Map::new();
+    columns.insert("fileid".into(), json!(file.fileid));
+    columns.insert("domain".into(), json!(file.domain));
+    columns.insert("relative_filename".into(), json!(file.relative_filename));
+    columns.insert("kind".into(), json!(format!("{:?}", file.kind())));
+    columns.insert("size".into(), json!(file.size()));
+    columns.insert(
+        "mode".into(),
+        json!(info.map(|info| info.file_mode().to_string())),
+    );
+    columns.insert(
+        "modified".into(),
+        json!(info
+            .and_then(|info| info.last_modified_date())
+            .map(|date| date.to_rfc3339())),
+    );
+
+    Row {
+        record: crate::stat::to_json(file),
+        columns,
+    }
+}
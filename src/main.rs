@@ -1,19 +1,30 @@
 #[macro_use]
 extern crate log;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 extern crate serde;
 
 extern crate clap;
 use clap::{App, Arg, SubCommand};
 
-mod lib;
-use lib::*;
-use std::io::Write;
+use ibackuptool2::*;
+#[cfg(unix)]
+mod agent;
+mod archive;
+mod extract;
+mod filters;
 mod infodump;
-use infodump::outputformat::*;
-
-const BACKUP_DIRECTORY: &'static str = "/Library/Application Support/MobileSync/Backup/";
+mod keycache;
+mod listing;
+mod metadata;
+mod output;
+mod password;
+mod stat;
+mod verify;
+use password::PasswordSource;
+
+/// Number of times the backup password is prompted for before giving up
+const PASSWORD_ATTEMPTS: usize = 3;
 
 fn main() {
     use env_logger::{Builder, Target};
@@ -32,22 +43,20 @@ fn main() {
                 .short("d")
                 .long("directory")
                 .value_name("DIR")
-                .help("Sets a custom backup origin folder.")
-                .takes_value(true),
+                .help("Sets a custom backup origin folder, searched recursively for backups. May be given more than once.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
         )
-        .subcommand(SubCommand::with_name("ls").about("lists backups or files within a backup"))
+        .args(&PasswordSource::args())
+        .arg(keycache::arg())
         .subcommand(
-            SubCommand::with_name("ls-files").arg(
-                Arg::with_name("BACKUP")
-                    .short("b")
-                    .long("backup")
-                    .value_name("BACKUP")
-                    .help("Sets a custom backup name / path. prepended to --directory.")
-                    .takes_value(true),
-            ),
+            SubCommand::with_name("ls")
+                .about("lists backups or files within a backup")
+                .arg(output::arg()),
         )
         .subcommand(
-            SubCommand::with_name("infodump")
+            SubCommand::with_name("ls-files")
                 .arg(
                     Arg::with_name("BACKUP")
                         .short("b")
@@ -57,12 +66,79 @@ fn main() {
                         .takes_value(true),
                 )
                 .arg(
-                    Arg::with_name("FORMAT")
-                        .short("f")
-                        .long("format")
-                        .value_name("FORMAT")
-                        .possible_values(&["json", "csv", "txt"])
-                        .help("Raw infodump capabilities.")
+                    Arg::with_name("JSON")
+                        .long("json")
+                        .help("Prints the files as a json array, same as --format json."),
+                )
+                .arg(output::arg().conflicts_with("JSON"))
+                .args(&filters::args()),
+        )
+        .subcommand(
+            SubCommand::with_name("stat")
+                .about("prints the manifest record of files within a backup")
+                .arg(
+                    Arg::with_name("BACKUP")
+                        .short("b")
+                        .long("backup")
+                        .value_name("BACKUP")
+                        .help("Sets a custom backup name / path. prepended to --directory.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("JSON")
+                        .long("json")
+                        .help("Prints the records as a json array."),
+                )
+                .args(&filters::args()),
+        )
+        .subcommand(
+            SubCommand::with_name("check-password")
+                .about("checks the backup password, without reading the manifest. exits non-zero if it is wrong.")
+                .arg(
+                    Arg::with_name("BACKUP")
+                        .short("b")
+                        .long("backup")
+                        .value_name("BACKUP")
+                        .help("Sets a custom backup name / path. prepended to --directory.")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("change-password")
+                .about("re-protects an encrypted backup with a new password. the backup is modified in place.")
+                .arg(
+                    Arg::with_name("BACKUP")
+                        .short("b")
+                        .long("backup")
+                        .value_name("BACKUP")
+                        .help("Sets a custom backup name / path. prepended to --directory.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ITERATIONS")
+                        .long("iterations")
+                        .value_name("N")
+                        .help("Sets the pbkdf2-sha1 iteration count of the new password. Defaults to the current one.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("DPIC")
+                        .long("dpic")
+                        .value_name("N")
+                        .help("Sets the pbkdf2-sha256 iteration count of the new password, on iOS 10.2+ backups. Defaults to the current one.")
+                        .takes_value(true),
+                )
+                .args(&PasswordSource::new_password_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("decrypt-backup")
+                .about("writes a decrypted copy of an encrypted backup, as a regular unencrypted backup")
+                .arg(
+                    Arg::with_name("BACKUP")
+                        .short("b")
+                        .long("backup")
+                        .value_name("BACKUP")
+                        .help("Sets a custom backup name / path. prepended to --directory.")
                         .takes_value(true),
                 )
                 .arg(
@@ -70,12 +146,14 @@ fn main() {
                         .short("o")
                         .long("dest")
                         .value_name("DEST")
-                        .help("Extract Destination.")
+                        .help("Directory to write the unencrypted backup to. Must be empty or not exist yet.")
+                        .required(true)
                         .takes_value(true),
                 ),
         )
         .subcommand(
-            SubCommand::with_name("find")
+            SubCommand::with_name("verify")
+                .about("checks that every file in the manifest is present, intact and decryptable. exits non-zero on problems.")
                 .arg(
                     Arg::with_name("BACKUP")
                         .short("b")
@@ -85,22 +163,34 @@ fn main() {
                         .takes_value(true),
                 )
                 .arg(
-                    Arg::with_name("PATH")
-                        .long("path")
-                        .value_name("PATH")
-                        .help("The relativeFilename to find")
+                    Arg::with_name("JSON")
+                        .long("json")
+                        .help("Prints the report as json instead of text."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("agent")
+                .about("keeps unlocked backup keys in memory, so other commands can skip the password")
+                .arg(
+                    Arg::with_name("SOCKET")
+                        .short("s")
+                        .long("socket")
+                        .value_name("SOCKET")
+                        .help("Unix socket to listen on. Defaults to a new private directory.")
                         .takes_value(true),
                 )
                 .arg(
-                    Arg::with_name("DOMAIN")
-                        .long("domain")
-                        .value_name("DOMAIN")
-                        .help("The domain to find")
+                    Arg::with_name("TIMEOUT")
+                        .short("t")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .help("Forget keys this long after they were added.")
+                        .default_value("3600")
                         .takes_value(true),
                 ),
         )
         .subcommand(
-            SubCommand::with_name("extract")
+            SubCommand::with_name("infodump")
                 .arg(
                     Arg::with_name("BACKUP")
                         .short("b")
@@ -109,15 +199,101 @@ fn main() {
                         .help("Sets a custom backup name / path. prepended to --directory.")
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("FORMAT")
+                        .short("f")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&infodump::ReportFormat::NAMES)
+                        .default_value("txt")
+                        .help("Output format of the reports: json, csv tables, or text.")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("DEST")
                         .short("o")
                         .long("dest")
                         .value_name("DEST")
-                        .help("Extract Destination.")
+                        .help("Extract Destination. Each report is written to a directory of its name in here.")
+                        .required_unless("LIST")
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("REPORT")
+                        .short("r")
+                        .long("report")
+                        .value_name("REPORT")
+                        .help("Comma separated reports to run, see --list. Runs every report by default.")
+                        .use_delimiter(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("LIST")
+                        .long("list")
+                        .help("Lists the available reports, and the files they read."),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("find")
+                .arg(
+                    Arg::with_name("BACKUP")
+                        .short("b")
+                        .long("backup")
+                        .value_name("BACKUP")
+                        .help("Sets a custom backup name / path. prepended to --directory.")
+                        .takes_value(true),
+                )
+                .args(&filters::args()),
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .arg(
+                    Arg::with_name("BACKUP")
+                        .short("b")
+                        .long("backup")
+                        .value_name("BACKUP")
+                        .help("Sets a custom backup name / path. prepended to --directory.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("DEST")
+                        .short("o")
+                        .long("dest")
+                        .value_name("DEST")
+                        .help("Extract Destination. With --format, the archive to write, or - for stdout.")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("FORMAT")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Writes a single archive instead of a directory tree.")
+                        .possible_values(&archive::ArchiveFormat::NAMES)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("PRESERVE")
+                        .short("p")
+                        .long("preserve")
+                        .help("Restores timestamps, permission bits and extended attributes of extracted files."),
+                )
+                .arg(
+                    Arg::with_name("STAY_IN_ROOT")
+                        .long("stay-in-root")
+                        .help("Skips files and symlinks that would point outside of the extract destination."),
+                )
+                .arg(
+                    Arg::with_name("JOBS")
+                        .short("j")
+                        .long("jobs")
+                        .value_name("N")
+                        .help("Number of files to decrypt and write in parallel.")
+                        .default_value("1")
+                        .takes_value(true),
+                )
+                .args(&filters::args()),
+        )
         .get_matches();
 
     // Gets a value for config if supplied by user, or defaults to "default.conf"
@@ -128,395 +304,741 @@ fn main() {
     // required we could have used an 'if let' to conditionally get the value)
     // println!("Using input file: {}", matches.value_of("INPUT").unwrap());
 
-    let home_dir = match dirs::home_dir() {
-        Some(res) => match res.to_str() {
-            Some(res) => res.to_string(),
-            None => panic!("Can't convert homedir to string!"),
-        },
-        None => panic!("Can't find homedir:"),
+    let roots: Vec<PathBuf> = match matches.values_of("DIR") {
+        Some(dirs) => dirs.map(PathBuf::from).collect(),
+        None => {
+            trace!("using default backup dirs, --directory not specified.");
+            discover::default_roots()
+        }
     };
 
-    let mut backup_dir = format!("{}{}", home_dir, BACKUP_DIRECTORY);
+    trace!("using src directories: {:?}", roots);
+
+    // You can handle information about subcommands by requesting their matches by name
+    // (as below), requesting just the name used, or both at the same time
+    if let Some(matches) = matches.subcommand_matches("ls") {
+        let backups = discover::discover(&roots);
+        if let Some(format) = output::from_matches(matches) {
+            let rows: Vec<output::Row> = backups
+                .iter()
+                .map(|path| listing::backup_row(path))
+                .collect();
+            output::print_rows(format, &listing::BACKUP_COLUMNS, &rows);
+            return;
+        }
+
+        for path in backups {
+            debug!("reading backup: {:?}", path);
+            match Backup::new(&path) {
+                Ok(backup) => {
+                    println!(
+                        "id={} name={} product={} iOS={} encrypted={:?} dir={:?}",
+                        backup.info.target_identifier,
+                        &backup
+                            .info
+                            .device_name
+                            .as_ref()
+                            .unwrap_or(&"<unnamed device>".to_string()),
+                        &backup
+                            .info
+                            .product_name
+                            .as_ref()
+                            .unwrap_or(&"<unknown product>".to_string()),
+                        backup.info.product_version,
+                        &backup.manifest.is_encrypted,
+                        &path,
+                    );
 
-    match matches.value_of("DIR") {
-        Some(dir) => backup_dir = dir.to_string(),
-        _ => {
-            trace!("using default backup dir, --directory not specified.")
+                    // if backup.manifest.is_encrypted {
+                    //     // Parse the manifest keybag
+                    //     backup.parse_keybag().unwrap();
+                    //     debug!("trying decrypt of backup keybag");
+
+                    //     // Unlock the keybag with password
+                    //     if let Some(ref mut kb) = backup.manifest.keybag.as_mut() {
+                    //         let pass = rpassword::read_password_from_tty(Some("Backup Password: "))
+                    //             .unwrap();
+                    //         kb.unlock_with_passcode(&pass); // TODO:
+                    //     }
+
+                    //     // Unlock the manifest key
+                    //     backup.manifest.unlock_manifest();
+
+                    //     // Parse the manifest
+                    //     backup.parse_manifest().unwrap();
+
+                    //     // now, unwrap all file keys in preparation of doing things; we can do this on a file-by-file basis also.
+                    //     backup.unwrap_file_keys().unwrap();
+                    // } else {
+                    //     backup.parse_manifest().unwrap();
+                    // }
+
+                    // info!("loaded {} files from manifest", backup.files.len());
+                    // info!(
+                    //     "loaded: {} domains from manifest",
+                    //     list_domains(&backup).len()
+                    // );
+                }
+                Err(err) => println!("error={:?} dir={:?}", err.to_string(), &path),
+            };
         }
     }
 
-    trace!("using src directory: {}", backup_dir);
-    let dir = Path::new(&backup_dir);
+    if let Some(matches) = matches.subcommand_matches("ls-files") {
+        let filter = filters::from_matches(matches);
+        let pathloc = matches
+            .value_of("BACKUP")
+            .expect("expect a backup be passed as an argument");
+        let path = find_backup(&roots, pathloc);
+        let format = if matches.is_present("JSON") {
+            Some(output::OutputFormat::Json)
+        } else {
+            output::from_matches(matches)
+        };
+        // keep stdout clean for machine readable output
+        let banner = if format.is_some() {
+            Banner::Stderr
+        } else {
+            Banner::Stdout
+        };
+        let backup = open_backup(&path, matches, banner);
+
+        info!("loaded {} files from manifest", backup.files.len());
+        info!(
+            "loaded: {} domains from manifest",
+            list_domains(&backup).len()
+        );
+
+        if let Some(format) = format {
+            let rows: Vec<output::Row> = backup
+                .find_files(&filter)
+                .into_iter()
+                .map(listing::file_row)
+                .collect();
+            output::print_rows(format, &listing::FILE_COLUMNS, &rows);
+            return;
+        }
 
-    if dir.is_dir() {
-        trace!("(backup directory exists!)");
+        for file in backup.find_files(&filter) {
+            println!(
+                "{}: {}, {}",
+                file.fileid, file.domain, file.relative_filename
+            );
+        }
     }
 
-    // You can handle information about subcommands by requesting their matches by name
-    // (as below), requesting just the name used, or both at the same time
-    if let Some(_matches) = matches.subcommand_matches("ls") {
-        let ls = std::fs::read_dir(dir).unwrap();
-
-        for entry in ls {
-            let entry = entry.unwrap();
-            if entry.path().is_dir() {
-                debug!("reading backup: {:?}", entry.path());
-                let path = entry.path();
-                match Backup::new(&path) {
-                    Ok(backup) => {
-                        println!(
-                            "id={} name={} product={} iOS={} encrypted={:?} dir={:?}",
-                            backup.info.target_identifier,
-                            &backup
-                                .info
-                                .device_name
-                                .as_ref()
-                                .unwrap_or(&"<unnamed device>".to_string()),
-                            &backup
-                                .info
-                                .product_name
-                                .as_ref()
-                                .unwrap_or(&"<unknown product>".to_string()),
-                            backup.info.product_version,
-                            &backup.manifest.is_encrypted,
-                            &path.file_name().unwrap(),
-                        );
-
-                        // if backup.manifest.is_encrypted {
-                        //     // Parse the manifest keybag
-                        //     backup.parse_keybag().unwrap();
-                        //     debug!("trying decrypt of backup keybag");
-
-                        //     // Unlock the keybag with password
-                        //     if let Some(ref mut kb) = backup.manifest.keybag.as_mut() {
-                        //         let pass = rpassword::read_password_from_tty(Some("Backup Password: "))
-                        //             .unwrap();
-                        //         kb.unlock_with_passcode(&pass); // TODO:
-                        //     }
-
-                        //     // Unlock the manifest key
-                        //     backup.manifest.unlock_manifest();
-
-                        //     // Parse the manifest
-                        //     backup.parse_manifest().unwrap();
-
-                        //     // now, unwrap all file keys in preparation of doing things; we can do this on a file-by-file basis also.
-                        //     backup.unwrap_file_keys().unwrap();
-                        // } else {
-                        //     backup.parse_manifest().unwrap();
-                        // }
-
-                        // info!("loaded {} files from manifest", backup.files.len());
-                        // info!(
-                        //     "loaded: {} domains from manifest",
-                        //     list_domains(&backup).len()
-                        // );
-                    }
-                    Err(err) => info!("failed to load {}: {:?}", err, path),
-                };
+    if let Some(matches) = matches.subcommand_matches("stat") {
+        let filter = filters::from_matches(matches);
+        let pathloc = matches
+            .value_of("BACKUP")
+            .expect("expect a backup be passed as an argument");
+        let path = find_backup(&roots, pathloc);
+        let backup = open_backup(&path, matches, Banner::Debug);
+
+        let files = backup.find_files(&filter);
+        if matches.is_present("JSON") {
+            print_json(&files);
+        } else {
+            for file in files {
+                stat::print(file);
             }
         }
     }
 
-    if let Some(matches) = matches.subcommand_matches("ls-files") {
+    if let Some(matches) = matches.subcommand_matches("check-password") {
         let pathloc = matches
             .value_of("BACKUP")
             .expect("expect a backup be passed as an argument");
-        let path = find_useful_folder(pathloc);
+        let path = find_backup(&roots, pathloc);
         debug!("reading backup: {:?}", &path);
-        match Backup::new(&path) {
+        match Backup::open(&path) {
             Ok(mut backup) => {
-                println!(
-                    "reading backup id={}, name={}, product={}, iOS={}, encrypted={:?}",
-                    backup.info.target_identifier,
-                    &backup
-                        .info
-                        .device_name
-                        .as_ref()
-                        .unwrap_or(&"<unnamed device>".to_string()),
-                    &backup
-                        .info
-                        .product_name
-                        .as_ref()
-                        .unwrap_or(&"<unknown product>".to_string()),
-                    backup.info.product_version,
-                    &backup.manifest.is_encrypted
-                );
+                if !backup.manifest.is_encrypted {
+                    println!("backup is not encrypted");
+                    return;
+                }
 
-                if backup.manifest.is_encrypted {
-                    // Parse the manifest keybag
-                    backup.parse_keybag().unwrap();
-                    debug!("trying decrypt of backup keybag");
+                if let Err(err) = backup.parse_keybag() {
+                    eprintln!("failed to read keybag: {}", err);
+                    std::process::exit(2);
+                }
 
-                    // Unlock the keybag with password
-                    if let Some(ref mut kb) = backup.manifest.keybag.as_mut() {
-                        let pass =
-                            rpassword::read_password_from_tty(Some("Backup Password: ")).unwrap();
-                        kb.unlock_with_passcode(&pass); // TODO:
+                let pass = match PasswordSource::from_matches(matches).read() {
+                    Ok(pass) => pass,
+                    Err(err) => {
+                        eprintln!("failed to read password: {}", err);
+                        std::process::exit(2);
                     }
+                };
 
-                    // Unlock the manifest key
-                    backup.manifest.unlock_manifest();
-
-                    // Parse the manifest
-                    backup.parse_manifest().expect("manifest to be parsed");
-
-                    // now, unwrap all file keys in preparation of doing things; we can do this on a file-by-file basis also.
-                    backup.unwrap_file_keys().unwrap();
-                } else {
-                    backup.parse_manifest().unwrap();
+                match backup.unlock_keybag(&pass) {
+                    Ok(_) => println!("password ok"),
+                    Err(BackupError::WrongPassword) => {
+                        eprintln!("wrong password");
+                        std::process::exit(1);
+                    }
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        std::process::exit(2);
+                    }
                 }
+            }
+            Err(err) => {
+                eprintln!("failed to load {:?}: {}", path, err);
+                std::process::exit(2);
+            }
+        };
+    }
 
-                info!("loaded {} files from manifest", backup.files.len());
-                info!(
-                    "loaded: {} domains from manifest",
-                    list_domains(&backup).len()
-                );
+    if let Some(matches) = matches.subcommand_matches("change-password") {
+        let pathloc = matches
+            .value_of("BACKUP")
+            .expect("expect a backup be passed as an argument");
+        let new_source = PasswordSource::new_password_from_matches(matches);
+        if new_source == PasswordSource::Stdin
+            && PasswordSource::from_matches(matches) == PasswordSource::Stdin
+        {
+            eprintln!("the current and the new password can't both be read from stdin");
+            std::process::exit(1);
+        }
 
-                for file in backup.files {
-                    println!(
-                        "{}: {}, {}",
-                        file.fileid, file.domain, file.relative_filename
-                    );
+        let count = |name: &str, flag: &str| match matches.value_of(name) {
+            None => None,
+            Some(value) => match value.parse::<u32>() {
+                Ok(count) if count > 0 => Some(count),
+                _ => {
+                    eprintln!("{} must be a positive number", flag);
+                    std::process::exit(1);
                 }
+            },
+        };
+        let iterations = count("ITERATIONS", "--iterations");
+        let dpic = count("DPIC", "--dpic");
+
+        let path = find_backup(&roots, pathloc);
+        let mut backup = open_backup(&path, matches, Banner::Debug);
+        if !backup.manifest.is_encrypted {
+            eprintln!("backup is not encrypted");
+            std::process::exit(1);
+        }
+
+        let pass = match new_source.read_new() {
+            Ok(pass) if pass.is_empty() => {
+                eprintln!("the new password can't be empty");
+                std::process::exit(1);
+            }
+            Ok(pass) => pass,
+            Err(err) => {
+                eprintln!("failed to read new password: {}", err);
+                std::process::exit(1);
             }
-            Err(err) => info!("failed to load {}: {:?}", err, path),
         };
+
+        if let Err(err) = backup.change_password(&pass, iterations, dpic) {
+            eprintln!("failed to change password: {}", err);
+            std::process::exit(1);
+        }
+
+        // cached keys for the old password no longer work
+        share_key(&backup, keycache::from_matches(matches).as_deref());
+        println!("password changed");
     }
 
-    if let Some(matches) = matches.subcommand_matches("find") {
-        let pathloc = matches.value_of("BACKUP").unwrap();
-        let path = find_useful_folder(pathloc);
+    if let Some(matches) = matches.subcommand_matches("decrypt-backup") {
+        let pathloc = matches
+            .value_of("BACKUP")
+            .expect("expect a backup be passed as an argument");
+        let dest = Path::new(matches.value_of("DEST").unwrap());
+        if std::fs::read_dir(dest)
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false)
+        {
+            eprintln!("{} is not empty", dest.display());
+            std::process::exit(1);
+        }
 
-        debug!("reading backup: {:?}", &path);
-        match Backup::new(&path) {
-            Ok(mut backup) => {
-                debug!(
-                    "reading backup id={}, name={}, product={}, iOS={}, encrypted={:?}",
-                    backup.info.target_identifier,
-                    &backup
-                        .info
-                        .device_name
-                        .as_ref()
-                        .unwrap_or(&"<unnamed device>".to_string()),
-                    &backup
-                        .info
-                        .product_name
-                        .as_ref()
-                        .unwrap_or(&"<unknown product>".to_string()),
-                    backup.info.product_version,
-                    &backup.manifest.is_encrypted
+        let path = find_backup(&roots, pathloc);
+        let backup = open_backup(&path, matches, Banner::Debug);
+        if !backup.manifest.is_encrypted {
+            eprintln!("backup is not encrypted");
+            std::process::exit(1);
+        }
+
+        let mut failed = 0;
+        let result = backup.write_decrypted(dest, |file, result| match result {
+            Ok(size) => println!(
+                "decrypt: {}/{}: {} bytes",
+                file.domain, file.relative_filename, size
+            ),
+            Err(err) => {
+                failed += 1;
+                error!(
+                    "failed to decrypt: {}/{}: {}",
+                    file.domain, file.relative_filename, err
                 );
+            }
+        });
 
-                if backup.manifest.is_encrypted {
-                    // Parse the manifest keybag
-                    backup.parse_keybag().unwrap();
-                    debug!("trying decrypt of backup keybag");
+        if let Err(err) = result {
+            eprintln!("failed to write backup: {}", err);
+            std::process::exit(1);
+        }
+        if failed > 0 {
+            eprintln!("{} files could not be decrypted", failed);
+            std::process::exit(1);
+        }
+    }
 
-                    // Unlock the keybag with password
-                    if let Some(ref mut kb) = backup.manifest.keybag.as_mut() {
-                        let pass =
-                            rpassword::read_password_from_tty(Some("Backup Password: ")).unwrap();
-                        kb.unlock_with_passcode(&pass); // TODO:
-                    }
+    if let Some(matches) = matches.subcommand_matches("verify") {
+        let pathloc = matches
+            .value_of("BACKUP")
+            .expect("expect a backup be passed as an argument");
+        let path = find_backup(&roots, pathloc);
+        let backup = open_backup(&path, matches, Banner::Debug);
+
+        let report = match backup.verify() {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("failed to verify {:?}: {}", path, err);
+                std::process::exit(1);
+            }
+        };
 
-                    // Unlock the manifest key
-                    backup.manifest.unlock_manifest();
+        if matches.is_present("JSON") {
+            match serde_json::to_string_pretty(&verify::to_json(&report)) {
+                Ok(json) => println!("{}", json),
+                Err(err) => error!("failed to write json: {}", err),
+            }
+        } else {
+            verify::print(&report);
+        }
 
-                    // Parse the manifest
-                    backup.parse_manifest().unwrap();
-                } else {
-                    backup.parse_manifest().unwrap();
-                }
+        if !report.is_ok() {
+            std::process::exit(1);
+        }
+    }
 
-                let mut file = backup
-                    .find_path(
-                        matches.value_of("DOMAIN").expect("--domain to be provided"),
-                        matches.value_of("PATH").expect("--path to be provided"),
-                    )
-                    .expect("File to exist");
+    if let Some(matches) = matches.subcommand_matches("agent") {
+        run_agent(matches);
+    }
 
-                if backup.manifest.is_encrypted {
-                    file.unwrap_file_key(&backup);
+    if let Some(matches) = matches.subcommand_matches("find") {
+        let filter = filters::from_matches(matches);
+        let pathloc = matches.value_of("BACKUP").unwrap();
+        let path = find_backup(&roots, pathloc);
+        let backup = open_backup(&path, matches, Banner::Debug);
+
+        let found = backup.find_files(&filter);
+        let file = match found.as_slice() {
+            [file] => *file,
+            [] => {
+                eprintln!("no file matches");
+                std::process::exit(1);
+            }
+            _ => {
+                eprintln!(
+                    "{} files match, narrow the filter down to one:",
+                    found.len()
+                );
+                for file in found {
+                    eprintln!(
+                        "{}: {}, {}",
+                        file.fileid, file.domain, file.relative_filename
+                    );
                 }
+                std::process::exit(1);
+            }
+        };
 
-                match backup.read_file(&file) {
-                    Ok(contents) => match std::io::stdout().write(&contents) {
-                        Ok(_) => {}
-                        Err(err) => error!("error: {}", err),
-                    },
-                    Err(err) => error!("error: {}", err),
+        match backup.open_file(file) {
+            Ok(mut reader) => {
+                let stdout = std::io::stdout();
+                let mut handle = stdout.lock();
+                if let Err(err) = std::io::copy(&mut reader, &mut handle) {
+                    error!("error: {}", err);
                 }
             }
-            Err(err) => info!("failed to load {}: {:?}", err, path),
-        };
+            Err(err) => error!("error: {}", err),
+        }
     }
 
     if let Some(matches) = matches.subcommand_matches("infodump") {
+        if matches.is_present("LIST") {
+            for report in infodump::registry() {
+                println!("{}: {}", report.name(), report.description());
+                for (domain, path) in report.sources() {
+                    println!("    reads {}/{}", domain, path);
+                }
+            }
+            return;
+        }
+
+        let reports = match matches.values_of("REPORT") {
+            Some(names) => match infodump::select(&names.collect::<Vec<&str>>()) {
+                Ok(reports) => reports,
+                Err(name) => {
+                    eprintln!(
+                        "unknown report `{}`, see --list for the available reports",
+                        name
+                    );
+                    std::process::exit(1);
+                }
+            },
+            None => infodump::registry(),
+        };
+        let format = matches
+            .value_of("FORMAT")
+            .and_then(infodump::ReportFormat::from_name)
+            .unwrap_or(infodump::ReportFormat::Text);
         let pathloc = matches.value_of("BACKUP").unwrap();
         let dest = Path::new(matches.value_of("DEST").unwrap());
-        let path = find_useful_folder(pathloc);
-        debug!("reading backup: {:?}", &path);
-        match Backup::new(&path) {
-            Ok(mut backup) => {
-                debug!(
-                    "reading backup id={}, name={}, product={}, iOS={}, encrypted={:?}",
-                    backup.info.target_identifier,
-                    &backup
-                        .info
-                        .device_name
-                        .as_ref()
-                        .unwrap_or(&"<unnamed device>".to_string()),
-                    &backup
-                        .info
-                        .product_name
-                        .as_ref()
-                        .unwrap_or(&"<unknown product>".to_string()),
-                    backup.info.product_version,
-                    &backup.manifest.is_encrypted
+        let path = find_backup(&roots, pathloc);
+        let backup = open_backup(&path, matches, Banner::Debug);
+
+        let mut failed = false;
+        for report in reports {
+            if let Some((domain, path)) = infodump::missing_source(report.as_ref(), &backup) {
+                eprintln!(
+                    "skipping {}: {}/{} is not in the backup",
+                    report.name(),
+                    domain,
+                    path
                 );
+                continue;
+            }
 
-                if backup.manifest.is_encrypted {
-                    // Parse the manifest keybag
-                    backup.parse_keybag().unwrap();
-                    debug!("trying decrypt of backup keybag");
-
-                    // Unlock the keybag with password
-                    if let Some(ref mut kb) = backup.manifest.keybag.as_mut() {
-                        let pass =
-                            rpassword::read_password_from_tty(Some("Backup Password: ")).unwrap();
-                        kb.unlock_with_passcode(&pass); // TODO:
-                    }
-
-                    // Unlock the manifest key
-                    backup.manifest.unlock_manifest();
-
-                    // Parse the manifest
-                    backup.parse_manifest().unwrap();
-                } else {
-                    backup.parse_manifest().unwrap();
+            let files = match report.run(&backup, format) {
+                Ok(files) => files,
+                Err(err) => {
+                    eprintln!("{} failed: {}", report.name(), err);
+                    failed = true;
+                    continue;
                 }
+            };
 
-                let smsr = infodump::SMSReader::load(&backup).unwrap();
-                let files = smsr.to_text(&backup).unwrap();
+            let report_dest = dest.join(report.name());
+            if let Err(err) = std::fs::create_dir_all(&report_dest) {
+                eprintln!("failed to create {}: {}", report_dest.display(), err);
+                failed = true;
+                continue;
+            }
 
-                for file in files {
-                    std::fs::write(
-                        dest.join(Path::new("sms/")).join(Path::new(&file.filename)),
-                        file.contents(),
-                    )
-                    .unwrap();
-                }
+            let written = files.iter().try_for_each(|file| {
+                let file_dest = report_dest.join(Path::new(&file.filename));
+                std::fs::write(&file_dest, file.contents())
+                    .map_err(|err| format!("{}: {}", file_dest.display(), err))
+            });
+            if let Err(err) = written {
+                eprintln!("{} failed to write {}", report.name(), err);
+                failed = true;
+                continue;
             }
-            Err(err) => info!("failed to load {}: {:?}", err, path),
-        };
+            println!(
+                "{}: wrote {} files to {}",
+                report.name(),
+                files.len(),
+                report_dest.display()
+            );
+        }
+
+        if failed {
+            std::process::exit(1);
+        }
     }
 
     if let Some(matches) = matches.subcommand_matches("extract") {
+        let filter = filters::from_matches(matches);
         let pathloc = matches.value_of("BACKUP").unwrap();
-        let extract_dest = Path::new(matches.value_of("DEST").unwrap());
-        let path = find_useful_folder(pathloc);
-        debug!("reading backup: {:?}", &path);
-        match Backup::new(&path) {
-            Ok(mut backup) => {
-                println!(
-                    "reading backup id={}, name={}, product={}, iOS={}, encrypted={:?}",
-                    backup.info.target_identifier,
-                    &backup
-                        .info
-                        .device_name
-                        .as_ref()
-                        .unwrap_or(&"<unnamed device>".to_string()),
-                    &backup
-                        .info
-                        .product_name
-                        .as_ref()
-                        .unwrap_or(&"<unknown product>".to_string()),
-                    backup.info.product_version,
-                    &backup.manifest.is_encrypted
-                );
+        let extract_dest = matches.value_of("DEST").unwrap();
+        let format = matches
+            .value_of("FORMAT")
+            .and_then(archive::ArchiveFormat::from_name);
+        let options = extract::ExtractOptions {
+            jobs: match matches.value_of("JOBS").unwrap().parse::<usize>() {
+                Ok(jobs) if jobs > 0 => jobs,
+                _ => {
+                    eprintln!("--jobs must be a positive number");
+                    std::process::exit(1);
+                }
+            },
+            preserve: matches.is_present("PRESERVE"),
+            stay_in_root: matches.is_present("STAY_IN_ROOT"),
+        };
+        let path = find_backup(&roots, pathloc);
+        // keep stdout clean when the archive is written there
+        let banner = if format.is_some() && extract_dest == "-" {
+            Banner::Stderr
+        } else {
+            Banner::Stdout
+        };
+        let backup = open_backup(&path, matches, banner);
+
+        info!("loaded {} files from manifest", backup.files.len());
+        info!(
+            "loaded: {} domains from manifest",
+            list_domains(&backup).len()
+        );
+
+        let files = backup.find_files(&filter);
+        if let Some(format) = format {
+            match archive::write_archive(
+                &backup,
+                &files,
+                extract_dest,
+                format,
+                options.stay_in_root,
+            ) {
+                Ok(0) => {}
+                Ok(failed) => {
+                    eprintln!("{} files could not be archived", failed);
+                    std::process::exit(1);
+                }
+                Err(err) => {
+                    eprintln!("failed to write archive: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            std::fs::create_dir_all(extract_dest).expect("directory creation to succeed");
+            let basepath = match Path::new(extract_dest).canonicalize() {
+                Ok(basepath) => basepath,
+                Err(err) => {
+                    eprintln!("failed to open {}: {}", extract_dest, err);
+                    std::process::exit(1);
+                }
+            };
 
-                if backup.manifest.is_encrypted {
-                    // Parse the manifest keybag
-                    backup.parse_keybag().unwrap();
-                    debug!("trying decrypt of backup keybag");
+            let failed = extract::extract(&backup, &files, &basepath, &options);
+            if failed > 0 {
+                eprintln!("{} files could not be extracted", failed);
+                std::process::exit(1);
+            }
+        }
+    }
+}
 
-                    // Unlock the keybag with password
-                    if let Some(ref mut kb) = backup.manifest.keybag.as_mut() {
-                        let pass =
-                            rpassword::read_password_from_tty(Some("Backup Password: ")).unwrap();
-                        kb.unlock_with_passcode(&pass); // TODO:
-                    }
+/// Print files as a json array.
+fn print_json(files: &[&BackupFile]) {
+    let records: Vec<serde_json::Value> = files.iter().map(|file| stat::to_json(file)).collect();
+    match serde_json::to_string_pretty(&records) {
+        Ok(json) => println!("{}", json),
+        Err(err) => error!("failed to write json: {}", err),
+    }
+}
+
+/// Find the backup `-b` names, see `discover::find_backup`. Exits if there is none.
+fn find_backup(roots: &[PathBuf], query: &str) -> PathBuf {
+    debug!("looking for backup: {:?}", query);
+    match discover::find_backup(roots, query) {
+        Ok(path) => path,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+}
 
-                    // Unlock the manifest key
-                    backup.manifest.unlock_manifest();
+/// Where `open_backup` prints the line describing the backup.
+#[derive(Clone, Copy)]
+enum Banner {
+    /// Only in the debug log
+    Debug,
+    Stdout,
+    /// Keeps stdout clean for machine readable output
+    Stderr,
+}
 
-                    // Parse the manifest
-                    backup.parse_manifest().unwrap();
+/// Open the backup, and unlock it with a cached key from `--key-file` or the agent,
+/// or a password from the source picked by the (sub)command arguments.
+/// The manifest is loaded too, exits if anything fails.
+fn open_backup(path: &Path, matches: &clap::ArgMatches, banner: Banner) -> Backup {
+    debug!("reading backup: {:?}", path);
+    let mut backup = match Backup::open(path) {
+        Ok(backup) => backup,
+        Err(err) => {
+            eprintln!("failed to load {:?}: {}", path, err);
+            std::process::exit(1);
+        }
+    };
 
-                    // now, unwrap all file keys in preparation of doing things; we can do this on a file-by-file basis also.
-                    backup.unwrap_file_keys().unwrap();
-                } else {
-                    backup.parse_manifest().unwrap();
-                }
+    let line = format!(
+        "reading backup id={}, name={}, product={}, iOS={}, encrypted={:?}",
+        backup.info.target_identifier,
+        backup
+            .info
+            .device_name
+            .as_deref()
+            .unwrap_or("<unnamed device>"),
+        backup
+            .info
+            .product_name
+            .as_deref()
+            .unwrap_or("<unknown product>"),
+        backup.info.product_version,
+        backup.manifest.is_encrypted
+    );
+    match banner {
+        Banner::Debug => debug!("{}", line),
+        Banner::Stdout => println!("{}", line),
+        Banner::Stderr => eprintln!("{}", line),
+    }
 
-                info!("loaded {} files from manifest", backup.files.len());
-                info!(
-                    "loaded: {} domains from manifest",
-                    list_domains(&backup).len()
+    if backup.manifest.is_encrypted {
+        if let Err(err) = backup.parse_keybag() {
+            eprintln!("failed to read keybag: {}", err);
+            std::process::exit(1);
+        }
+
+        let key_file = keycache::from_matches(matches);
+        if !unlock_from_cache(&mut backup, key_file.as_deref()) {
+            return unlock_with_password(path, matches, key_file.as_deref());
+        }
+    }
+
+    // Parse the manifest, and unwrap all file keys in preparation of doing things
+    if let Err(err) = backup.load_manifest() {
+        eprintln!("failed to read manifest: {}", err);
+        std::process::exit(1);
+    }
+
+    backup
+}
+
+/// Open and unlock the backup with a password from the source picked by the (sub)command arguments.
+/// Re-prompts on a wrong password, and exits once `PASSWORD_ATTEMPTS` have failed.
+/// Non-interactive sources are only read once.
+fn unlock_with_password(
+    path: &Path,
+    matches: &clap::ArgMatches,
+    key_file: Option<&Path>,
+) -> Backup {
+    debug!("trying decrypt of backup keybag");
+    let source = PasswordSource::from_matches(matches);
+
+    let attempts = if source.is_interactive() {
+        PASSWORD_ATTEMPTS
+    } else {
+        1
+    };
+
+    for attempt in 1..=attempts {
+        let pass = match source.read() {
+            Ok(pass) => pass,
+            Err(err) => {
+                eprintln!("failed to read password: {}", err);
+                std::process::exit(1);
+            }
+        };
+
+        // unlocking takes the backup, so every attempt starts from a freshly opened one
+        match Backup::open(path).and_then(|backup| backup.unlock(&pass)) {
+            Ok(backup) => {
+                share_key(&backup, key_file);
+                return backup;
+            }
+            Err(BackupError::WrongPassword) if !source.is_interactive() => {
+                eprintln!("wrong password.");
+                std::process::exit(1);
+            }
+            Err(BackupError::WrongPassword) => {
+                eprintln!(
+                    "wrong password, try again. ({}/{})",
+                    attempt, PASSWORD_ATTEMPTS
                 );
+            }
+            Err(err) => {
+                eprintln!("failed to unlock backup: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
 
-                let basepath = Path::new(extract_dest);
-                std::fs::create_dir_all(&basepath).expect("directory creation to succeed");
-
-                for file in &backup.files {
-                    let filepath = basepath
-                        .join(Path::new(&file.domain))
-                        .join(Path::new(&file.relative_filename));
-
-                    match &backup.read_file(&file) {
-                        Ok(res) => {
-                            std::fs::create_dir_all(
-                                &filepath.parent().expect("expect path to have a parent"),
-                            )
-                            .expect("directory creation to succeed");
-                            println!("extract: {}: {} bytes", filepath.display(), res.len());
-                            std::fs::write(filepath, res)
-                                .expect("to be able to write file contents");
-                        }
-                        Err(err) => {
-                            error!("failed to extract: {}: {}", filepath.display(), err);
-                        }
-                    }
-                    // println!("{}: {}, {}", file.fileid, file.domain, file.relative_filename);
+    eprintln!("too many wrong password attempts.");
+    std::process::exit(1);
+}
+
+/// Unlock the keybag with a key held by the agent, or cached in the key file.
+/// Returns false if neither has a working key.
+fn unlock_from_cache(backup: &mut Backup, key_file: Option<&Path>) -> bool {
+    #[cfg(unix)]
+    {
+        if let Some(socket) = agent::socket_from_env() {
+            if agent::unlock(backup, &socket) {
+                return true;
+            }
+        }
+    }
+
+    if let Some(path) = key_file {
+        if keycache::unlock(backup, path) {
+            #[cfg(unix)]
+            {
+                if let Some(socket) = agent::socket_from_env() {
+                    agent::store(backup, &socket);
                 }
             }
-            Err(err) => info!("failed to load {}: {:?}", err, path),
-        };
+            return true;
+        }
     }
+
+    false
 }
 
-fn find_useful_folder(dirname: &str) -> std::path::PathBuf {
-    let path = Path::new(dirname);
+/// Hand the key of a keybag unlocked with a password to the key file and the agent.
+fn share_key(backup: &Backup, key_file: Option<&Path>) {
+    if let Some(path) = key_file {
+        keycache::store(backup, path);
+    }
 
-    debug!("check useful? {:?}", path.display());
-    if path.is_dir() {
-        return dirname.into();
+    #[cfg(unix)]
+    {
+        if let Some(socket) = agent::socket_from_env() {
+            agent::store(backup, &socket);
+        }
     }
+}
 
-    let home_dir = match dirs::home_dir() {
-        Some(res) => match res.to_str() {
-            Some(res) => res.to_string(),
-            None => panic!("Can't convert homedir to string!"),
-        },
-        None => panic!("Can't find homedir:"),
+/// Run the key agent in the foreground, printing the shell command to point other commands at it.
+#[cfg(unix)]
+fn run_agent(matches: &clap::ArgMatches) {
+    let timeout = match matches.value_of("TIMEOUT").unwrap().parse::<u64>() {
+        Ok(timeout) => std::time::Duration::from_secs(timeout),
+        Err(_) => {
+            eprintln!("--timeout must be a number of seconds");
+            std::process::exit(1);
+        }
     };
 
-    let backup_dir = format!("{}{}", home_dir, BACKUP_DIRECTORY);
+    let socket = match matches.value_of("SOCKET") {
+        Some(socket) => std::path::PathBuf::from(socket),
+        None => match agent::default_socket() {
+            Ok(socket) => socket,
+            Err(err) => {
+                eprintln!("failed to create agent socket directory: {}", err);
+                std::process::exit(1);
+            }
+        },
+    };
 
-    let dir = Path::new(&backup_dir);
+    println!(
+        "{}={}; export {};",
+        agent::AGENT_SOCK_ENV,
+        socket.display(),
+        agent::AGENT_SOCK_ENV
+    );
+    use std::io::Write;
+    let _ = std::io::stdout().flush();
+
+    if let Err(err) = agent::run(&socket, timeout) {
+        eprintln!("agent failed: {}", err);
+        std::process::exit(1);
+    }
+}
 
-    return dir.join(Path::new(dirname));
+#[cfg(not(unix))]
+fn run_agent(_matches: &clap::ArgMatches) {
+    eprintln!("the agent is only supported on unix");
+    std::process::exit(1);
 }
 
 fn list_domains(backup: &Backup) -> Vec<String> {
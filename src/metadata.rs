@@ -0,0 +1,123 @@
+use ibackuptool2::*;
+use std::path::Path;
+
+/// Restore the metadata of an extracted file from the manifest:
+/// extended attributes, permission bits and timestamps.
+///
+/// Extended attributes the filesystem doesn't accept are skipped with a warning.
+/// Only `Manifest.mbdb` backups record the access time, otherwise it is set to
+/// the modification time.
+pub fn restore(file: &BackupFile, path: &Path) -> Result<(), BackupError> {
+    let info = match file.fileinfo {
+        Some(ref info) => info,
+        None => return Ok(()),
+    };
+
+    #[cfg(unix)]
+    restore_xattrs(info, path);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = info.file_mode().permissions();
+        if mode != 0 {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+    }
+
+    let (atime, mtime) = file_times(info);
+    filetime::set_file_times(path, atime, mtime)?;
+
+    Ok(())
+}
+
+/// Restore the timestamps of an extracted symlink, without following it.
+pub fn restore_symlink(file: &BackupFile, path: &Path) -> Result<(), BackupError> {
+    if let Some(ref info) = file.fileinfo {
+        let (atime, mtime) = file_times(info);
+        filetime::set_symlink_file_times(path, atime, mtime)?;
+    }
+
+    Ok(())
+}
+
+/// Access and modification time of a file, the access time falling back to
+/// the modification time when the manifest doesn't have it.
+fn file_times(info: &FileInfo) -> (filetime::FileTime, filetime::FileTime) {
+    let mtime = filetime::FileTime::from_unix_time(info.last_modified as i64, 0);
+    let atime = match info.last_accessed {
+        Some(atime) => filetime::FileTime::from_unix_time(atime as i64, 0),
+        None => mtime,
+    };
+    (atime, mtime)
+}
+
+/// Set the extended attributes of the file. Linux only allows namespaced
+/// attribute names, so the names are stored in the `user.` namespace there.
+#[cfg(unix)]
+fn restore_xattrs(info: &FileInfo, path: &Path) {
+    for (name, value) in &info.extended_attributes {
+        #[cfg(target_os = "linux")]
+        let name = format!("user.{}", name);
+
+        if let Err(err) = xattr::set(path, &name, value) {
+            warn!("can't set {} on {}: {}", name, path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(mode: u64, last_accessed: Option<u64>) -> BackupFile {
+        BackupFile {
+            fileid: String::new(),
+            domain: "HomeDomain".to_string(),
+            relative_filename: "Library/a".to_string(),
+            flags: 1,
+            fileinfo: Some(FileInfo {
+                last_modified: 1_500_000_000,
+                last_status_change: 0,
+                last_accessed,
+                birth: 0,
+                flags: 0,
+                inode: 0,
+                group_id: 0,
+                user_id: 0,
+                size: 0,
+                mode,
+                protection_class: ProtectionClass::from(0),
+                wrapped_encryption_key: None,
+                wrapped_encryption_class: None,
+                encryption_key: None,
+                extended_attributes: std::collections::HashMap::new(),
+                target: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_restore() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let path = tmp.path();
+
+        restore(&file(0o100600, Some(1_400_000_000)), path).unwrap();
+        let meta = std::fs::metadata(path).unwrap();
+        let mtime = filetime::FileTime::from_last_modification_time(&meta);
+        let atime = filetime::FileTime::from_last_access_time(&meta);
+        assert_eq!(mtime.unix_seconds(), 1_500_000_000);
+        assert_eq!(atime.unix_seconds(), 1_400_000_000);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(meta.permissions().mode() & 0o7777, 0o600);
+        }
+
+        // without an access time, it is set to the modification time
+        restore(&file(0o100644, None), path).unwrap();
+        let meta = std::fs::metadata(path).unwrap();
+        let atime = filetime::FileTime::from_last_access_time(&meta);
+        assert_eq!(atime.unix_seconds(), 1_500_000_000);
+    }
+}
@@ -0,0 +1,181 @@
+use clap::Arg;
+use serde_json::{Map, Value};
+use std::io::Write;
+
+/// Formats listings can be printed in with `--format`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// A pretty printed json array
+    Json,
+    /// One json object per line
+    Jsonl,
+    /// Comma separated values, with a header row
+    Csv,
+    /// Aligned columns, for reading
+    Table,
+}
+
+impl OutputFormat {
+    /// Format names accepted by `--format`
+    pub const NAMES: [&'static str; 4] = ["json", "jsonl", "csv", "table"];
+
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "json" => Some(OutputFormat::Json),
+            "jsonl" => Some(OutputFormat::Jsonl),
+            "csv" => Some(OutputFormat::Csv),
+            "table" => Some(OutputFormat::Table),
+            _ => None,
+        }
+    }
+}
+
+/// The `--format` argument of listing commands.
+pub fn arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("FORMAT")
+        .long("format")
+        .value_name("FORMAT")
+        .help("Prints the listing in a machine readable format. json and jsonl include every field, csv and table the columns.")
+        .possible_values(&OutputFormat::NAMES)
+        .takes_value(true)
+}
+
+/// The format chosen with `--format`, if any.
+pub fn from_matches(matches: &clap::ArgMatches) -> Option<OutputFormat> {
+    matches.value_of("FORMAT").and_then(OutputFormat::from_name)
+}
+
+/// A listing entry: its full json record, and the flat values of its columns.
+pub struct Row {
+    pub record: Value,
+    pub columns: Map<String, Value>,
+}
+
+/// Show a column value as text. Strings are unquoted, and missing values are empty.
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// Quote a csv field if it contains a separator, quote or line break.
+fn csv_field(text: &str) -> String {
+    if text.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Write a listing in `format`, with `columns` in order for csv and table.
+pub fn write_rows<W: Write>(
+    out: &mut W,
+    format: OutputFormat,
+    columns: &[&str],
+    rows: &[Row],
+) -> std::io::Result<()> {
+    match format {
+        OutputFormat::Json => {
+            let records: Vec<&Value> = rows.iter().map(|row| &row.record).collect();
+            writeln!(out, "{}", serde_json::to_string_pretty(&records)?)?;
+        }
+        OutputFormat::Jsonl => {
+            for row in rows {
+                writeln!(out, "{}", serde_json::to_string(&row.record)?)?;
+            }
+        }
+        OutputFormat::Csv => {
+            let header: Vec<String> = columns.iter().map(|name| csv_field(name)).collect();
+            writeln!(out, "{}", header.join(","))?;
+            for row in rows {
+                let fields: Vec<String> = columns
+                    .iter()
+                    .map(|name| csv_field(&cell(row.columns.get(*name))))
+                    .collect();
+                writeln!(out, "{}", fields.join(","))?;
+            }
+        }
+        OutputFormat::Table => {
+            let cells: Vec<Vec<String>> = rows
+                .iter()
+                .map(|row| {
+                    columns
+                        .iter()
+                        .map(|name| cell(row.columns.get(*name)))
+                        .collect()
+                })
+                .collect();
+
+            let widths: Vec<usize> = columns
+                .iter()
+                .enumerate()
+                .map(|(i, name)| {
+                    cells
+                        .iter()
+                        .map(|row| row[i].chars().count())
+                        .chain(std::iter::once(name.len()))
+                        .max()
+                        .unwrap_or(0)
+                })
+                .collect();
+
+            let line = |values: Vec<&str>| {
+                let padded: Vec<String> = values
+                    .iter()
+                    .zip(&widths)
+                    .map(|(value, width)| format!("{:<width$}", value, width = width))
+                    .collect();
+                padded.join("  ").trim_end().to_string()
+            };
+
+            writeln!(out, "{}", line(columns.to_vec()))?;
+            for row in &cells {
+                writeln!(
+                    out,
+                    "{}",
+                    line(row.iter().map(|cell| cell.as_str()).collect())
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Print a listing to stdout.
+pub fn print_rows(format: OutputFormat, columns: &[&str], rows: &[Row]) {
+    let stdout = std::io::stdout();
+    if let Err(err) = write_rows(&mut stdout.lock(), format, columns, rows) {
+        error!("failed to write listing: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rows() -> Vec<Row> {
+        let record = json!({"id": "a,b", "size": 10, "error": null});
+        let columns = record.as_object().unwrap().clone();
+        vec![Row { record, columns }]
+    }
+
+    fn written(format: OutputFormat) -> String {
+        let mut out = vec![];
+        write_rows(&mut out, format, &["id", "size", "error"], &rows()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_write_rows() {
+        assert_eq!(written(OutputFormat::Csv), "id,size,error\n\"a,b\",10,\n");
+        assert_eq!(written(OutputFormat::Table), "id   size  error\na,b  10\n");
+        assert_eq!(
+            written(OutputFormat::Jsonl),
+            "{\"error\":null,\"id\":\"a,b\",\"size\":10}\n"
+        );
+    }
+}
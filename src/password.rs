@@ -0,0 +1,138 @@
+use clap::{Arg, ArgMatches};
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Where the backup password is read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PasswordSource {
+    /// Prompt on the terminal
+    Prompt,
+    /// Read from an environment variable
+    Env(String),
+    /// Read the first line of a file
+    File(PathBuf),
+    /// Read the first line of stdin
+    Stdin,
+}
+
+impl PasswordSource {
+    /// The global arguments used to pick a password source.
+    pub fn args() -> Vec<Arg<'static, 'static>> {
+        vec![
+            Arg::with_name("PASSWORD_FILE")
+                .long("password-file")
+                .value_name("FILE")
+                .help("Reads the backup password from the first line of a file, or stdin if '-'.")
+                .takes_value(true)
+                .global(true),
+            Arg::with_name("PASSWORD_ENV")
+                .long("password-env")
+                .value_name("VAR")
+                .help("Reads the backup password from an environment variable.")
+                .takes_value(true)
+                .conflicts_with("PASSWORD_FILE")
+                .global(true),
+        ]
+    }
+
+    /// The `change-password` arguments used to pick where the new password is read from.
+    pub fn new_password_args() -> Vec<Arg<'static, 'static>> {
+        vec![
+            Arg::with_name("NEW_PASSWORD_FILE")
+                .long("new-password-file")
+                .value_name("FILE")
+                .help("Reads the new password from the first line of a file, or stdin if '-'.")
+                .takes_value(true),
+            Arg::with_name("NEW_PASSWORD_ENV")
+                .long("new-password-env")
+                .value_name("VAR")
+                .help("Reads the new password from an environment variable.")
+                .takes_value(true)
+                .conflicts_with("NEW_PASSWORD_FILE"),
+        ]
+    }
+
+    /// Pick the password source from the (sub)command arguments.
+    pub fn from_matches(matches: &ArgMatches) -> PasswordSource {
+        PasswordSource::from_args(matches, "PASSWORD_FILE", "PASSWORD_ENV")
+    }
+
+    /// Pick the new password source from the `change-password` arguments.
+    pub fn new_password_from_matches(matches: &ArgMatches) -> PasswordSource {
+        PasswordSource::from_args(matches, "NEW_PASSWORD_FILE", "NEW_PASSWORD_ENV")
+    }
+
+    fn from_args(matches: &ArgMatches, file: &str, env: &str) -> PasswordSource {
+        if let Some(var) = matches.value_of(env) {
+            return PasswordSource::Env(var.to_string());
+        }
+
+        match matches.value_of(file) {
+            Some("-") => PasswordSource::Stdin,
+            Some(path) => PasswordSource::File(PathBuf::from(path)),
+            None => PasswordSource::Prompt,
+        }
+    }
+
+    /// Whether a wrong password can be retried by asking again.
+    pub fn is_interactive(&self) -> bool {
+        *self == PasswordSource::Prompt
+    }
+
+    /// Read the password from this source.
+    pub fn read(&self) -> std::io::Result<String> {
+        match self {
+            PasswordSource::Prompt => rpassword::read_password_from_tty(Some("Backup Password: ")),
+            PasswordSource::Env(var) => std::env::var(var).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("environment variable {} is not set", var),
+                )
+            }),
+            PasswordSource::File(path) => Ok(first_line(&std::fs::read_to_string(path)?)),
+            PasswordSource::Stdin => {
+                let mut contents = String::new();
+                std::io::stdin().read_to_string(&mut contents)?;
+                Ok(first_line(&contents))
+            }
+        }
+    }
+
+    /// Read a new password from this source. The prompt asks twice, to catch typos.
+    pub fn read_new(&self) -> std::io::Result<String> {
+        if !self.is_interactive() {
+            return self.read();
+        }
+
+        let pass = rpassword::read_password_from_tty(Some("New Backup Password: "))?;
+        let repeated = rpassword::read_password_from_tty(Some("Repeat New Password: "))?;
+        if pass != repeated {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the passwords don't match",
+            ));
+        }
+
+        Ok(pass)
+    }
+}
+
+/// The first line of a password file, without its line ending.
+/// Other whitespace is kept, since it may be part of the password.
+fn first_line(contents: &str) -> String {
+    let line = contents.split('\n').next().unwrap_or("");
+    line.strip_suffix('\r').unwrap_or(line).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_line() {
+        assert_eq!(first_line("hunter2\n"), "hunter2");
+        assert_eq!(first_line("hunter2\r\nsecond line\n"), "hunter2");
+        assert_eq!(first_line(" spaced pass "), " spaced pass ");
+        assert_eq!(first_line(""), "");
+    }
+}
@@ -0,0 +1,140 @@
+use chrono::{DateTime, Utc};
+use ibackuptool2::*;
+use serde_json::{json, Map, Value};
+
+/// Show an attribute value as text if it is printable, and as hex otherwise.
+fn attribute_text(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(text) if !text.chars().any(|c| c.is_control() && c != '\0') => {
+            text.trim_end_matches('\0').to_string()
+        }
+        _ => hex::encode(value),
+    }
+}
+
+/// Show an optional date as rfc3339, or `-` if it isn't recorded.
+fn date_text(date: Option<DateTime<Utc>>) -> String {
+    date.map(|date| date.to_rfc3339())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Print a file record as `key: value` lines.
+pub fn print(file: &BackupFile) {
+    println!("{}/{}", file.domain, file.relative_filename);
+    println!("  fileid: {}", file.fileid);
+    println!("  kind: {:?}", file.kind());
+    println!("  size: {}", file.size());
+
+    let info = match file.fileinfo {
+        Some(ref info) => info,
+        None => return,
+    };
+
+    let mode = info.file_mode();
+    println!("  mode: {} ({:o})", mode, mode.bits);
+    println!("  flags: {} ({:#x})", info.file_flags(), info.flags);
+    println!("  uid: {}", info.user_id);
+    println!("  gid: {}", info.group_id);
+    println!("  inode: {}", info.inode);
+    println!("  modified: {}", date_text(info.last_modified_date()));
+    println!("  changed: {}", date_text(info.last_status_change_date()));
+    println!("  born: {}", date_text(info.birth_date()));
+    println!("  protection class: {:?}", info.protection_class);
+    println!(
+        "  encryption key: {}",
+        if info.wrapped_encryption_key.is_some() {
+            "wrapped"
+        } else {
+            "-"
+        }
+    );
+
+    if let Some(ref target) = info.target {
+        println!("  target: {}", target);
+    }
+
+    let mut names: Vec<&String> = info.extended_attributes.keys().collect();
+    names.sort();
+    for name in names {
+        println!(
+            "  xattr {}: {}",
+            name,
+            attribute_text(&info.extended_attributes[name])
+        );
+    }
+
+    if let Some(quarantine) = info.quarantine() {
+        println!(
+            "  quarantine: agent={} date={} flags={:#x} event={}",
+            quarantine.agent,
+            date_text(quarantine.date()),
+            quarantine.flags,
+            quarantine.event_id.as_deref().unwrap_or("-")
+        );
+    }
+
+    if let Some(urls) = info.where_froms() {
+        for url in urls {
+            println!("  where from: {}", url);
+        }
+    }
+}
+
+/// A file record as json, for listings.
+pub fn to_json(file: &BackupFile) -> Value {
+    let mut out = Map::new();
+    out.insert("fileid".into(), json!(file.fileid));
+    out.insert("domain".into(), json!(file.domain));
+    out.insert("relative_filename".into(), json!(file.relative_filename));
+    out.insert("kind".into(), json!(format!("{:?}", file.kind())));
+    out.insert("size".into(), json!(file.size()));
+
+    if let Some(ref info) = file.fileinfo {
+        let mode = info.file_mode();
+        out.insert("mode".into(), json!(mode.bits));
+        out.insert("permissions".into(), json!(mode.to_string()));
+        out.insert("flags".into(), json!(info.file_flags().names()));
+        out.insert("user_id".into(), json!(info.user_id));
+        out.insert("group_id".into(), json!(info.group_id));
+        out.insert("inode".into(), json!(info.inode));
+        for (key, date) in [
+            ("last_modified", info.last_modified_date()),
+            ("last_status_change", info.last_status_change_date()),
+            ("birth", info.birth_date()),
+        ]
+        .iter()
+        {
+            out.insert((*key).into(), json!(date.map(|date| date.to_rfc3339())));
+        }
+        out.insert(
+            "protection_class".into(),
+            json!(format!("{:?}", info.protection_class)),
+        );
+        out.insert("target".into(), json!(info.target));
+
+        let attributes: Map<String, Value> = info
+            .extended_attributes
+            .iter()
+            .map(|(name, value)| (name.clone(), json!(attribute_text(value))))
+            .collect();
+        out.insert("extended_attributes".into(), Value::Object(attributes));
+
+        if let Some(quarantine) = info.quarantine() {
+            out.insert(
+                "quarantine".into(),
+                json!({
+                    "flags": quarantine.flags,
+                    "date": quarantine.date().map(|date| date.to_rfc3339()),
+                    "agent": quarantine.agent,
+                    "event_id": quarantine.event_id,
+                }),
+            );
+        }
+
+        if let Some(urls) = info.where_froms() {
+            out.insert("where_froms".into(), json!(urls));
+        }
+    }
+
+    Value::Object(out)
+}
@@ -0,0 +1,74 @@
+use ibackuptool2::*;
+use serde_json::{json, Value};
+
+/// Print every problem on its own line, followed by a summary.
+pub fn print(report: &VerifyReport) {
+    for issue in &report.issues {
+        println!(
+            "{}: {}/{} ({}): {}",
+            issue.problem.name(),
+            issue.domain,
+            issue.relative_filename,
+            issue.fileid,
+            issue.problem
+        );
+    }
+
+    for path in &report.orphans {
+        println!("orphan: {}: not in the manifest", path);
+    }
+
+    println!(
+        "checked {} records and {} files: {} problems, {} orphans",
+        report.files,
+        report.blobs,
+        report.issues.len(),
+        report.orphans.len()
+    );
+    println!(
+        "{}",
+        if report.is_ok() {
+            "backup ok"
+        } else {
+            "backup has problems"
+        }
+    );
+}
+
+/// The report as json, with a summary and every problem found.
+pub fn to_json(report: &VerifyReport) -> Value {
+    let issues: Vec<Value> = report
+        .issues
+        .iter()
+        .map(|issue| {
+            let mut out = json!({
+                "fileid": issue.fileid,
+                "domain": issue.domain,
+                "relative_filename": issue.relative_filename,
+                "problem": issue.problem.name(),
+                "message": issue.problem.to_string(),
+            });
+
+            match &issue.problem {
+                VerifyProblem::FileIdMismatch { expected } => {
+                    out["expected_fileid"] = json!(expected);
+                }
+                VerifyProblem::SizeMismatch { expected, actual } => {
+                    out["expected_size"] = json!(expected);
+                    out["actual_size"] = json!(actual);
+                }
+                _ => {}
+            }
+
+            out
+        })
+        .collect();
+
+    json!({
+        "ok": report.is_ok(),
+        "records": report.files,
+        "files": report.blobs,
+        "issues": issues,
+        "orphans": report.orphans,
+    })
+}